@@ -0,0 +1,470 @@
+// This module implements shell completion support.
+// Copyright (c) 2015 by Shipeng Feng.
+// Licensed under the BSD License, see LICENSE for more details.
+
+use std::env;
+use std::ascii::AsciiExt;
+
+use core::{Command, Context};
+use types::Options;
+
+
+const BASH_SOURCE: &'static str = "\
+%(complete_func)s() {
+    local IFS=$'\\n'
+    local response
+
+    response=$(env COMP_WORDS=\"${COMP_WORDS[*]}\" COMP_CWORD=$COMP_CWORD \
+%(complete_var)s=bash_complete $1)
+
+    for completion in $response; do
+        IFS=',' read type value <<< \"$completion\"
+
+        if [[ $type == 'file' ]]; then
+            COMPREPLY=()
+            compopt -o default
+        elif [[ $type == 'plain' ]]; then
+            COMPREPLY+=($value)
+        fi
+    done
+
+    return 0
+}
+
+complete -o nosort -F %(complete_func)s %(prog_name)s
+";
+
+const ZSH_SOURCE: &'static str = "\
+#compdef %(prog_name)s
+
+%(complete_func)s() {
+    local -a completions
+    local -a completions_with_descriptions
+    local -a response
+    (( ! $+commands[%(prog_name)s] )) && return 1
+
+    response=(\"${(@f)$(env COMP_WORDS=\"${words[*]}\" COMP_CWORD=$((CURRENT-1)) \
+%(complete_var)s=zsh_complete %(prog_name)s)}\")
+
+    for type key descr in ${response}; do
+        if [[ \"$type\" == \"plain\" ]]; then
+            if [[ \"$descr\" == \"_\" ]]; then
+                completions+=(\"$key\")
+            else
+                completions_with_descriptions+=(\"$key\":\"$descr\")
+            fi
+        elif [[ \"$type\" == \"file\" ]]; then
+            _path_files -f
+        fi
+    done
+
+    if [ -n \"$completions_with_descriptions\" ]; then
+        _describe -V unsorted completions_with_descriptions -U
+    fi
+
+    if [ -n \"$completions\" ]; then
+        compadd -U -V unsorted -a completions
+    fi
+}
+
+compdef %(complete_func)s %(prog_name)s
+";
+
+const FISH_SOURCE: &'static str = "\
+function %(complete_func)s
+    set -l response (env %(complete_var)s=fish_complete COMP_WORDS=(commandline -cp) \
+COMP_CWORD=(commandline -t) %(prog_name)s)
+
+    for completion in $response
+        set -l metadata (string split \",\" $completion)
+
+        if test $metadata[1] = \"file\"
+            __fish_complete_path $metadata[2]
+        else if test $metadata[1] = \"plain\"
+            echo $metadata[2]
+        end
+    end
+end
+
+complete --no-files --command %(prog_name)s --arguments \"(%(complete_func)s)\"
+";
+
+
+/// One completion result.  The `file` kind asks the shell to complete paths.
+pub struct CompletionItem {
+    pub value: String,
+    pub kind: &'static str,
+    pub help: Option<String>,
+}
+
+impl CompletionItem {
+    /// Create one new plain completion item.
+    pub fn new(value: &str, help: Option<String>) -> CompletionItem {
+        CompletionItem {
+            value: value.to_string(),
+            kind: "plain",
+            help: help,
+        }
+    }
+
+    /// Create one item that lets the shell complete file paths.
+    pub fn file(incomplete: &str) -> CompletionItem {
+        CompletionItem {
+            value: incomplete.to_string(),
+            kind: "file",
+            help: None,
+        }
+    }
+}
+
+
+fn get_complete_var(prog_name: &str) -> String {
+    let name: String = prog_name.chars().map(|c| {
+        if c.is_alphanumeric() { c.to_ascii_uppercase() } else { '_' }
+    }).collect();
+    format!("_{}_COMPLETE", name)
+}
+
+
+fn get_source(shell: &str, prog_name: &str) -> Option<String> {
+    let template = match shell {
+        "bash" => BASH_SOURCE,
+        "zsh"  => ZSH_SOURCE,
+        "fish" => FISH_SOURCE,
+        _      => return None,
+    };
+    let complete_func = format!("_{}_completion", prog_name.replace("-", "_"));
+    Some(template.replace("%(complete_func)s", complete_func.as_slice())
+                 .replace("%(complete_var)s", get_complete_var(prog_name).as_slice())
+                 .replace("%(prog_name)s", prog_name))
+}
+
+
+fn find_option<'a>(options: &'a Vec<Options>, opt: &str) -> Option<&'a Options> {
+    options.iter().find(|option| {
+        option.get_opts().iter().any(|name| name.as_slice() == opt)
+    })
+}
+
+
+/// Check the argument starts with `-` or the custom prefix of an option.
+fn is_option_like(options: &Vec<Options>, arg: &str) -> bool {
+    match arg.chars().next() {
+        Some('-') => true,
+        Some(c) => options.iter().any(|option| {
+            option.get_opts().iter().any(|name| name.starts_with(c.to_string().as_slice()))
+        }),
+        None => false,
+    }
+}
+
+
+/// Walks the already typed arguments and returns the completions for the
+/// incomplete one.
+pub fn get_completions(root: &Command, args: &[String], incomplete: &str) -> Vec<CompletionItem> {
+    let mut command = root;
+    let mut ctx = Context::new(root.get_name());
+    let mut options = command.get_options();
+    let mut used: Vec<String> = Vec::new();
+    let mut positional: usize = 0;
+    let mut pending: Option<Options> = None;
+
+    for arg in args.iter() {
+        match pending {
+            Some(ref option) => {
+                let mut values = ctx.params.get_all(option.get_name());
+                values.push(arg.clone());
+                ctx.params.insert(option.get_name(), values);
+            },
+            None => (),
+        }
+        if pending.is_some() {
+            pending = None;
+            continue;
+        }
+        if arg.len() > 1 && is_option_like(&options, arg.as_slice()) {
+            match find_option(&options, arg.as_slice()) {
+                Some(option) => {
+                    used.push_all(option.get_opts().as_slice());
+                    if option.takes_value() {
+                        pending = Some(option.clone());
+                    }
+                },
+                None => (),
+            }
+            continue;
+        }
+        if command.has_commands() {
+            match command.resolve_command(arg.as_slice()) {
+                Ok(subcommand) => {
+                    ctx = ctx.child(subcommand.get_name());
+                    command = subcommand;
+                    options = command.get_options();
+                    used = Vec::new();
+                    positional = 0;
+                    continue;
+                },
+                Err(_) => (),
+            }
+        }
+        match command.get_arguments().get(positional) {
+            Some(argument) => ctx.params.insert(argument.get_name(), vec![arg.clone()]),
+            None => (),
+        }
+        positional = positional + 1;
+    }
+
+    let mut items: Vec<CompletionItem> = Vec::new();
+    match pending {
+        Some(option) => {
+            match option.get_completion() {
+                Some(completion) => return completion(&ctx, incomplete),
+                None => (),
+            }
+            if option.get_choices().is_empty() {
+                items.push(CompletionItem::file(incomplete));
+            }
+            for choice in option.get_choices().iter() {
+                if choice.starts_with(incomplete) {
+                    items.push(CompletionItem::new(*choice, None));
+                }
+            }
+            return items;
+        },
+        None => (),
+    }
+
+    if is_option_like(&options, incomplete) {
+        for option in options.iter().filter(|option| !option.is_hidden()) {
+            for opt in option.get_opts().iter() {
+                if opt.starts_with(incomplete) &&
+                   (option.is_multiple() || !used.contains(opt)) {
+                    items.push(CompletionItem::new(opt.as_slice(),
+                                                   Some(option.get_help().to_string())));
+                }
+            }
+        }
+    } else if command.has_commands() {
+        for subcommand in command.get_commands().iter() {
+            if !subcommand.is_hidden() && subcommand.get_name().starts_with(incomplete) {
+                items.push(CompletionItem::new(subcommand.get_name(),
+                                               Some(subcommand.get_short_help())));
+            }
+        }
+    } else if positional < command.get_arguments().len() {
+        let argument = &command.get_arguments()[positional];
+        if argument.is_hidden() {
+            return items;
+        }
+        match argument.get_completion() {
+            Some(completion) => return completion(&ctx, incomplete),
+            None => items.push(CompletionItem::file(incomplete)),
+        }
+    }
+    items
+}
+
+
+fn format_item(shell: &str, item: &CompletionItem) -> String {
+    let help = match item.help {
+        Some(ref help) if !help.is_empty() => help.clone(),
+        _ => String::new(),
+    };
+    match shell {
+        "zsh" => {
+            let descr = if help.is_empty() { "_".to_string() } else { help };
+            format!("{}\n{}\n{}", item.kind, item.value, descr)
+        },
+        "fish" if !help.is_empty() => format!("{},{}\t{}", item.kind, item.value, help),
+        _ => format!("{},{}", item.kind, item.value),
+    }
+}
+
+
+/// Returns the typed arguments and the incomplete one from the `COMP_WORDS`
+/// and `COMP_CWORD` values the shell script sets up.
+fn get_completion_args(shell: &str, comp_words: &str, comp_cword: &str) -> (Vec<String>, String) {
+    let mut words: Vec<String> = comp_words.split(' ')
+                                           .filter(|word| !word.is_empty())
+                                           .map(|word| word.to_string())
+                                           .collect();
+    if !words.is_empty() {
+        words.remove(0);
+    }
+    if shell == "fish" {
+        // Fish passes the current token instead of its index.
+        if !comp_cword.is_empty() && words.last().map(|word| word.as_slice()) == Some(comp_cword) {
+            words.pop();
+        }
+        return (words, comp_cword.to_string());
+    }
+    let cword: usize = comp_cword.parse().unwrap_or(0);
+    if cword == 0 {
+        return (words, String::new());
+    }
+    let incomplete = if cword - 1 < words.len() {
+        words[cword - 1].clone()
+    } else {
+        String::new()
+    };
+    words.truncate(cword - 1);
+    (words, incomplete)
+}
+
+
+/// Returns the output for the completion instruction, eg. `bash_source` for
+/// the completion script or `bash_complete` for the completions.
+fn get_completion_output(command: &Command, prog_name: &str, instruction: &str,
+                         comp_words: &str, comp_cword: &str) -> String {
+    let mut parts = instruction.splitn(1, '_');
+    let shell = parts.next().unwrap_or("");
+    let action = parts.next().unwrap_or("");
+    let mut output = String::new();
+    match action {
+        "source" => {
+            match get_source(shell, prog_name) {
+                Some(source) => output.push_str(source.as_slice()),
+                None => (),
+            }
+        },
+        "complete" => {
+            let (args, incomplete) = get_completion_args(shell, comp_words, comp_cword);
+            let items = get_completions(command, args.as_slice(), incomplete.as_slice());
+            for item in items.iter() {
+                output.push_str(format_item(shell, item).as_slice());
+                output.push_str("\n");
+            }
+        },
+        _ => (),
+    }
+    output
+}
+
+
+/// The hook for shell completion.  If the completion env var is set this
+/// prints either the completion script or the completions and returns
+/// `true`, the command should exit without running the callbacks then.
+pub fn bashcomplete(command: &Command, prog_name: &str) -> bool {
+    let instruction = match env::var(get_complete_var(prog_name).as_slice()) {
+        Ok(instruction) => instruction,
+        Err(_) => return false,
+    };
+    let comp_words = env::var("COMP_WORDS").unwrap_or(String::new());
+    let comp_cword = env::var("COMP_CWORD").unwrap_or(String::new());
+    print!("{}", get_completion_output(command, prog_name, instruction.as_slice(),
+                                       comp_words.as_slice(), comp_cword.as_slice()));
+    true
+}
+
+
+#[cfg(test)]
+mod tests {
+    use core::{Command, Context};
+    use types::Params;
+    use super::{CompletionItem, get_completions, get_completion_args, get_completion_output};
+
+    fn noop(_: Params) {}
+
+    fn make_tool() -> Command {
+        let mut tool = Command::new("tool", noop);
+        let mut remove = Command::new("remove", noop);
+        remove.short_help("Remove a file.");
+        remove.option("f", "force", "Never prompt.", true, true, false, false, None);
+        remove.option("c", "color", "", false, false, false, false, None)
+              .choices(vec!["red", "blue"]);
+        remove.option("o", "output", "", false, false, false, false, None);
+        tool.add_command(remove);
+        tool.add_command(Command::new("status", noop));
+        tool
+    }
+
+    fn values(items: Vec<CompletionItem>) -> Vec<String> {
+        items.into_iter().map(|item| format!("{},{}", item.kind, item.value)).collect()
+    }
+
+    fn complete(args: &[&str], incomplete: &str) -> Vec<String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        values(get_completions(&make_tool(), args.as_slice(), incomplete))
+    }
+
+    #[test]
+    fn test_complete_subcommands() {
+        assert_eq!(complete(&[], "re"), vec!["plain,remove"]);
+        assert_eq!(complete(&[], ""), vec!["plain,remove", "plain,status"]);
+    }
+
+    #[test]
+    fn test_complete_options() {
+        assert_eq!(complete(&["remove"], "--f"), vec!["plain,--force"]);
+        // Options given once are not offered again.
+        assert_eq!(complete(&["remove", "--force"], "--f"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_complete_choices_and_files() {
+        assert_eq!(complete(&["remove", "--color"], "b"), vec!["plain,blue"]);
+        assert_eq!(complete(&["remove", "-o"], "out"), vec!["file,out"]);
+    }
+
+    #[test]
+    fn test_completion_args() {
+        // Bash and zsh pass the index of the current word, the program name
+        // included.
+        let expected = (vec!["remove".to_string()], "--f".to_string());
+        assert_eq!(get_completion_args("bash", "tool remove --f", "2"), expected);
+        assert_eq!(get_completion_args("zsh", "tool remove --f", "2"), expected);
+        assert_eq!(get_completion_args("bash", "tool remove ", "2"),
+                   (vec!["remove".to_string()], String::new()));
+        assert_eq!(get_completion_args("bash", "tool", "0"), (Vec::new(), String::new()));
+        // Fish passes the current token instead.
+        assert_eq!(get_completion_args("fish", "tool remove --f", "--f"), expected);
+        assert_eq!(get_completion_args("fish", "tool remove ", ""),
+                   (vec!["remove".to_string()], String::new()));
+    }
+
+    #[test]
+    fn test_completion_output() {
+        let tool = make_tool();
+        let source = get_completion_output(&tool, "tool", "bash_source", "", "");
+        assert!(source.contains("_TOOL_COMPLETE=bash_complete $1"));
+        assert!(source.contains("complete -o nosort -F _tool_completion tool"));
+        assert_eq!(get_completion_output(&tool, "tool", "bash_complete", "tool re", "1"),
+                   "plain,remove\n");
+        assert_eq!(get_completion_output(&tool, "tool", "zsh_complete", "tool re", "1"),
+                   "plain\nremove\nRemove a file.\n");
+        assert_eq!(get_completion_output(&tool, "tool", "fish_complete", "tool re", "re"),
+                   "plain,remove\tRemove a file.\n");
+        assert_eq!(get_completion_output(&tool, "tool", "tcsh_source", "", ""), "");
+    }
+
+    fn complete_branch(ctx: &Context, incomplete: &str) -> Vec<CompletionItem> {
+        let cluster = ctx.params.get("cluster").unwrap_or("none".to_string());
+        vec![CompletionItem::new(format!("{}-{}", cluster, incomplete).as_slice(),
+                                 Some("Branch.".to_string()))]
+    }
+
+    #[test]
+    fn test_custom_completion() {
+        let mut deploy = Command::new("deploy", noop);
+        deploy.option("", "cluster", "", false, false, false, false, None);
+        deploy.option("", "branch", "", false, false, false, false, None)
+              .completion(complete_branch);
+        deploy.argument("target", true, None).completion(complete_branch);
+        let args = vec!["--cluster".to_string(), "prod".to_string(), "--branch".to_string()];
+        let items = get_completions(&deploy, args.as_slice(), "ma");
+        assert_eq!(values(items), vec!["plain,prod-ma"]);
+        let items = get_completions(&deploy, &[], "x");
+        assert_eq!(values(items), vec!["plain,none-x"]);
+    }
+
+    #[test]
+    fn test_hidden_argument_not_completed() {
+        let mut deploy = Command::new("deploy", noop);
+        {
+            let target = deploy.argument("target", true, None);
+            target.completion(complete_branch);
+            target.hidden(true);
+        }
+        assert!(get_completions(&deploy, &[], "x").is_empty());
+    }
+}
@@ -0,0 +1,194 @@
+// This module implements configuration file support.
+// Copyright (c) 2015 by Shipeng Feng.
+// Licensed under the BSD License, see LICENSE for more details.
+
+use std::env;
+use std::io::Read;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+
+
+/// Returns the config folder for the application, `$XDG_CONFIG_HOME/app` or
+/// `~/.config/app`.
+pub fn get_app_dir(app_name: &str) -> PathBuf {
+    let base = match env::var("XDG_CONFIG_HOME") {
+        Ok(ref dir) if !dir.is_empty() => PathBuf::new(dir),
+        _ => {
+            let home = env::home_dir().unwrap_or(PathBuf::new("."));
+            home.join(".config")
+        },
+    };
+    base.join(app_name)
+}
+
+
+fn normalize_key(key: &str) -> String {
+    key.trim().replace("_", "-")
+}
+
+
+fn parse_value(value: &str) -> Vec<String> {
+    let value = value.trim();
+    if value.starts_with("[") && value.ends_with("]") {
+        return value[1..value.len() - 1].split(',')
+                                        .map(|item| item.trim())
+                                        .filter(|item| !item.is_empty())
+                                        .map(unquote)
+                                        .collect();
+    }
+    vec![unquote(value)]
+}
+
+
+fn unquote(value: &str) -> String {
+    if value.len() >= 2 &&
+       ((value.starts_with("\"") && value.ends_with("\"")) ||
+        (value.starts_with("'") && value.ends_with("'"))) {
+        value[1..value.len() - 1].replace("\\\"", "\"")
+    } else {
+        value.to_string()
+    }
+}
+
+
+/// Settings loaded from a TOML or INI style file.  Keys before any section
+/// belong to the top level command, sections are named after the
+/// subcommand path, eg. `[remote.add]`.  Examples:
+///
+/// ```text
+/// verbose = true
+///
+/// [remove]
+/// force = yes
+/// exclude = ["*.log", "*.tmp"]
+/// ```
+///
+#[derive(Clone)]
+pub struct Config {
+    sections: HashMap<String, HashMap<String, Vec<String>>>,
+}
+
+impl Config {
+    /// Create one new empty config.
+    pub fn new() -> Config {
+        Config {
+            sections: HashMap::new(),
+        }
+    }
+
+    /// Parses the text of a config file.
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let mut config = Config::new();
+        let mut section = String::new();
+        for (lineno, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("#") || line.starts_with(";") {
+                continue;
+            }
+            if line.starts_with("[") && line.ends_with("]") {
+                section = line[1..line.len() - 1].trim().to_string();
+                continue;
+            }
+            let mut parts = line.splitn(1, '=');
+            let key = parts.next().unwrap_or("");
+            let value = match parts.next() {
+                Some(value) => value,
+                None => return Err(format!("line {}: expected \"key = value\"", lineno + 1)),
+            };
+            if !config.sections.contains_key(&section) {
+                config.sections.insert(section.clone(), HashMap::new());
+            }
+            config.sections.get_mut(&section).unwrap()
+                  .insert(normalize_key(key), parse_value(value));
+        }
+        Ok(config)
+    }
+
+    /// Loads and parses a config file.
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let mut text = String::new();
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) => return Err(format!("could not open {}: {}", path.display(), e)),
+        };
+        match file.read_to_string(&mut text) {
+            Ok(_) => (),
+            Err(e) => return Err(format!("could not read {}: {}", path.display(), e)),
+        }
+        match Config::parse(text.as_slice()) {
+            Ok(config) => Ok(config),
+            Err(message) => Err(format!("{}: {}", path.display(), message)),
+        }
+    }
+
+    /// Returns the values of a key in a section, `""` is the top level.
+    pub fn get(&self, section: &str, key: &str) -> Option<Vec<String>> {
+        match self.sections.get(section) {
+            Some(values) => values.get(&normalize_key(key)).map(|values| values.clone()),
+            None => None,
+        }
+    }
+}
+
+
+/// Default values set by the caller, overriding the static defaults of
+/// options and arguments.  Subcommands get their own nested map.  Examples:
+///
+/// ```rust,no_run
+/// use std::os;
+/// use cli::{Command, DefaultMap, Params};
+///
+/// fn tool(_: Params) {}
+///
+/// let command = Command::new("tool", tool);
+/// let mut defaults = DefaultMap::new();
+/// defaults.command("remote").command("add").set("branch", "main");
+/// let args: Vec<String> = os::args().into_iter().skip(1).collect();
+/// command.invoke_with_defaults("tool".to_string(), args, defaults).unwrap();
+/// ```
+///
+#[derive(Clone)]
+pub struct DefaultMap {
+    values: HashMap<String, Vec<String>>,
+    commands: HashMap<String, DefaultMap>,
+}
+
+impl DefaultMap {
+    /// Create one new empty default map.
+    pub fn new() -> DefaultMap {
+        DefaultMap {
+            values: HashMap::new(),
+            commands: HashMap::new(),
+        }
+    }
+
+    /// Sets the default of a parameter.
+    pub fn set(&mut self, name: &str, value: &str) {
+        self.values.insert(normalize_key(name), vec![value.to_string()]);
+    }
+
+    /// Sets the defaults of a parameter taking multiple values.
+    pub fn set_all(&mut self, name: &str, values: Vec<&str>) {
+        self.values.insert(normalize_key(name),
+                           values.iter().map(|value| value.to_string()).collect());
+    }
+
+    /// Returns the nested map of a subcommand, creating it if needed.
+    pub fn command(&mut self, name: &str) -> &mut DefaultMap {
+        if !self.commands.contains_key(name) {
+            self.commands.insert(name.to_string(), DefaultMap::new());
+        }
+        self.commands.get_mut(name).unwrap()
+    }
+
+    /// Returns the defaults of a parameter.
+    pub fn get(&self, name: &str) -> Option<Vec<String>> {
+        self.values.get(&normalize_key(name)).map(|values| values.clone())
+    }
+
+    /// Returns the nested map of a subcommand.
+    pub fn get_command(&self, name: &str) -> Option<&DefaultMap> {
+        self.commands.get(name)
+    }
+}
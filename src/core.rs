@@ -3,14 +3,100 @@
 // Licensed under the BSD License, see LICENSE for more details.
 
 use std::os;
-use std::path::Path;
+use std::env;
+use std::ascii::AsciiExt;
+use std::cmp::{min, max};
+use std::process;
+use std::old_io;
+use std::path::{Path, PathBuf};
 use std::slice::SliceConcatExt;
 
-use getopts;
+use types::{Params, CommandCallback, ParamCallback, ParamSource, Constraint};
+use types::{Options, Argument, TokenNormalizeFunc};
+use formatting::{HelpFormatter, Theme};
+use parser::{OptionParser, expand_response_files};
+use utils::{get_close_matches, normalize_token};
+use completion::bashcomplete;
+use schema::dump_schema;
+use term::{isatty, get_terminal_size};
+use config::{Config, DefaultMap, get_app_dir};
 
-use types::{Params, CommandCallback};
-use types::{Options, Argument};
-use formatting::HelpFormatter;
+
+/// The context holds the state of one command invocation, it is what
+/// parameter callbacks receive.
+pub struct Context {
+    pub command_path: String,  // The names of the invoked commands, eg. `tool remove`
+    pub params: Params,  // The params processed so far
+    pub config: Option<Config>,  // The loaded config file, inherited by subcommands
+    pub default_map: Option<DefaultMap>,  // The caller's defaults for this command
+    pub extra_args: Vec<String>,  // The leftover arguments, if the command allows them
+    pub token_normalize_func: Option<TokenNormalizeFunc>,  // Inherited by subcommands
+}
+
+impl Context {
+    /// Create one new context for the top level command.
+    pub fn new(info_name: &str) -> Context {
+        Context {
+            command_path: info_name.to_string(),
+            params: Params::new(),
+            config: None,
+            default_map: None,
+            extra_args: Vec::new(),
+            token_normalize_func: None,
+        }
+    }
+
+    /// Create the context for a subcommand of this context.
+    pub fn child(&self, info_name: &str) -> Context {
+        Context {
+            command_path: format!("{} {}", self.command_path, info_name),
+            params: Params::new(),
+            config: self.config.clone(),
+            default_map: match self.default_map {
+                Some(ref default_map) => default_map.get_command(info_name).cloned(),
+                None => None,
+            },
+            extra_args: Vec::new(),
+            token_normalize_func: self.token_normalize_func,
+        }
+    }
+
+    /// Returns the caller's default of a parameter of this command.
+    pub fn lookup_default_map(&self, name: &str) -> Option<Vec<String>> {
+        match self.default_map {
+            Some(ref default_map) => default_map.get(name),
+            None => None,
+        }
+    }
+
+    /// The config section of this command, the subcommand path joined by
+    /// dots, eg. `remote.add` for `tool remote add`.
+    pub fn config_section(&self) -> String {
+        let names: Vec<&str> = self.command_path.as_slice().split(' ').skip(1).collect();
+        names.connect(".")
+    }
+}
+
+
+/// Parses the value of the `COLUMNS` env var, only positive widths count.
+fn parse_columns(columns: Option<String>) -> Option<usize> {
+    match columns {
+        Some(columns) => match columns.as_slice().trim().parse::<usize>() {
+            Ok(columns) if columns > 0 => Some(columns),
+            _ => None,
+        },
+        None => None,
+    }
+}
+
+
+/// Refers to one parameter of a command, used to keep the declaration order
+/// of options and arguments.
+#[derive(Clone, Copy, PartialEq)]
+enum ParamRef {
+    Opt(usize),
+    Arg(usize),
+}
 
 
 /// The command is the basic type of command line applications in cli.  This
@@ -23,6 +109,27 @@ pub struct Command {
     help: String,  // The help message to use for this command
     epilog: String,  // Printed at the end of the help page
     short_help: String,  // The short help to use for this command, this is shown on the command listing of the parent command
+    aliases: Vec<String>,  // Alternative names this command can be invoked with from the parent command
+    commands: Vec<Command>,  // The subcommands registered with this command
+    allow_prefix_match: bool,  // Resolve any unambiguous prefix of a subcommand name
+    dump_schema_option: bool,  // Accept a hidden `--dump-schema` option that prints the JSON schema
+    max_content_width: usize,  // The maximum width of the help output, even on wider terminals
+    terminal_width: Option<usize>,  // The width of the terminal instead of the detected one
+    theme: Theme,  // The styles of the help output, only used on terminals
+    sections: Vec<(String, String)>,  // Extra help sections, eg. Examples, as (title, text)
+    section_order: Vec<String>,  // The titles of the help sections to show first, in order
+    hidden: bool,  // Hide this command from the command listing of the parent command
+    deprecated: bool,  // Print a warning when this command is invoked
+    deprecated_hint: Option<String>,  // The replacement shown in the deprecation warning
+    constraints: Vec<Constraint>,  // Constraints between the parameters, validated after parsing
+    show_constraints: bool,  // Describe the constraints in a help section
+    config_app_name: Option<String>,  // Load option defaults from this application's config file
+    param_order: Vec<ParamRef>,  // The options and arguments in declaration order
+    response_files: bool,  // Expand `@path` arguments into the arguments in the file
+    allow_interspersed_args: Option<bool>,  // Allow options after positional arguments
+    ignore_unknown_options: bool,  // Keep unknown options as extra arguments
+    allow_extra_args: bool,  // Collect leftover arguments instead of failing
+    token_normalize_func: Option<TokenNormalizeFunc>,  // Normalizes tokens before matching
 }
 
 
@@ -36,39 +143,268 @@ impl Command {
             help: String::new(),
             epilog: String::new(),
             short_help: String::new(),
+            aliases: Vec::new(),
+            commands: Vec::new(),
+            allow_prefix_match: false,
+            dump_schema_option: false,
+            max_content_width: 80,
+            terminal_width: None,
+            theme: Theme::new(),
+            sections: Vec::new(),
+            section_order: Vec::new(),
+            hidden: false,
+            deprecated: false,
+            deprecated_hint: None,
+            constraints: Vec::new(),
+            show_constraints: false,
+            config_app_name: None,
+            param_order: Vec::new(),
+            response_files: false,
+            allow_interspersed_args: None,
+            ignore_unknown_options: false,
+            allow_extra_args: false,
+            token_normalize_func: None,
         }
     }
 
-    /// Attaches an option to the command.
+    /// Sets the help message shown on the help page.
+    pub fn help(&mut self, help: &str) {
+        self.help = help.to_string();
+    }
+
+    /// Sets the text printed at the end of the help page.
+    pub fn epilog(&mut self, epilog: &str) {
+        self.epilog = epilog.to_string();
+    }
+
+    /// Sets the short help shown on the command listing of the parent command.
+    pub fn short_help(&mut self, short_help: &str) {
+        self.short_help = short_help.to_string();
+    }
+
+    /// Adds an alternative name for this command, eg. `rm` for `remove`.
+    pub fn alias(&mut self, alias: &str) {
+        self.aliases.push(alias.to_string());
+    }
+
+    /// Enable or disable resolving subcommands by any unambiguous prefix,
+    /// eg. `tool stat` for `tool status`.
+    pub fn allow_prefix_match(&mut self, allow: bool) {
+        self.allow_prefix_match = allow;
+    }
+
+    /// Enable or disable the hidden `--dump-schema` option, which prints the
+    /// JSON description of this command and its subcommands and exits.
+    pub fn dump_schema_option(&mut self, enable: bool) {
+        self.dump_schema_option = enable;
+    }
+
+    /// Returns the JSON description of this command and its subcommands.
+    pub fn get_schema(&self) -> String {
+        dump_schema(self)
+    }
+
+    /// Sets the maximum width of the help output, defaults to 80.
+    pub fn max_content_width(&mut self, width: usize) {
+        self.max_content_width = width;
+    }
+
+    /// Sets the width of the terminal the help is formatted for, instead of
+    /// detecting it.
+    pub fn terminal_width(&mut self, width: usize) {
+        self.terminal_width = Some(width);
+    }
+
+    /// Sets the styles of the help output.  Use `Theme::plain()` to disable
+    /// styling.  Styles are only used for the help printed by an invocation
+    /// on a terminal, `get_help` is always plain.
+    pub fn theme(&mut self, theme: Theme) {
+        self.theme = theme;
+    }
+
+    /// Adds an extra section to the help page, eg. Examples or Exit status.
+    pub fn section(&mut self, title: &str, text: &str) {
+        self.sections.push((title.to_string(), text.to_string()));
+    }
+
+    /// Sets the order of the help sections.  Option groups, "Options",
+    /// "Commands" and extra sections can be named, the rest follows in the
+    /// default order.
+    pub fn section_order(&mut self, order: Vec<&str>) {
+        self.section_order = order.iter().map(|name| name.to_string()).collect();
+    }
+
+    /// Hides this command from the help and completion of the parent
+    /// command, it can still be invoked.
+    pub fn hidden(&mut self, hidden: bool) {
+        self.hidden = hidden;
+    }
+
+    /// Marks this command as deprecated, invoking it prints a warning with
+    /// the optional replacement hint.
+    pub fn deprecated(&mut self, hint: Option<&str>) {
+        self.deprecated = true;
+        self.deprecated_hint = hint.map(|hint| hint.to_string());
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        self.hidden
+    }
+
+    pub fn is_deprecated(&self) -> bool {
+        self.deprecated
+    }
+
+    /// Adds a constraint between parameters, eg. mutually exclusive options.
+    pub fn constraint(&mut self, constraint: Constraint) {
+        self.constraints.push(constraint);
+    }
+
+    /// Enable or disable describing the constraints in the help page.
+    pub fn show_constraints(&mut self, show: bool) {
+        self.show_constraints = show;
+    }
+
+    /// Loads option defaults from a config file.  The file is given by the
+    /// `--config` option, the `APP_CONFIG` env var, or is the `config` file in
+    /// the application's config folder, eg. `~/.config/app/config`.
+    pub fn config_file(&mut self, app_name: &str) {
+        self.config_app_name = Some(app_name.to_string());
+    }
+
+    /// Enable or disable expanding `@path` arguments into the arguments read
+    /// from the file, before parsing.  `@@` escapes a literal leading `@`.
+    pub fn allow_response_files(&mut self, allow: bool) {
+        self.response_files = allow;
+    }
+
+    /// Enable or disable options after positional arguments.  By default
+    /// they are allowed, except for commands with subcommands, where the
+    /// first positional argument is the subcommand.
+    pub fn allow_interspersed_args(&mut self, allow: bool) {
+        self.allow_interspersed_args = Some(allow);
+    }
+
+    /// Enable or disable keeping unknown options as positional arguments
+    /// instead of failing, eg. for commands wrapping another program.
+    pub fn ignore_unknown_options(&mut self, ignore: bool) {
+        self.ignore_unknown_options = ignore;
+    }
+
+    /// Enable or disable collecting leftover arguments into the extra args
+    /// of the context instead of failing.  They keep the order given.
+    pub fn allow_extra_args(&mut self, allow: bool) {
+        self.allow_extra_args = allow;
+    }
+
+    /// Sets a func to normalize option names, subcommand names and choice
+    /// values before matching, eg. to accept `--Verbose`.  Subcommands
+    /// inherit it.
+    pub fn token_normalize_func(&mut self, func: TokenNormalizeFunc) {
+        self.token_normalize_func = Some(func);
+    }
+
+    /// Registers a subcommand with this command.
+    pub fn add_command(&mut self, command: Command) {
+        self.commands.push(command);
+    }
+
+    /// Attaches an option to the command.  Returns the option so that it can
+    /// be further configured.
     pub fn option(&mut self, short_name: &'static str, long_name: &'static str, help: &'static str,
                   is_flag: bool, is_bool_flag: bool, multiple: bool,
-                  required: bool, default: Option<&'static str>) {
+                  required: bool, default: Option<&'static str>) -> &mut Options {
         let option = Options::new(short_name, long_name, help, is_flag,
                                   is_bool_flag, multiple, required, default);
         self.options.push(option);
+        self.param_order.push(ParamRef::Opt(self.options.len() - 1));
+        self.options.last_mut().unwrap()
     }
 
-    /// Attaches an argument to the command.
-    pub fn argument(&mut self, name: &'static str, required: bool, default: Option<&'static str>) {
+    /// Attaches an argument to the command.  Returns the argument so that it
+    /// can be further configured.
+    pub fn argument(&mut self, name: &'static str, required: bool,
+                    default: Option<&'static str>) -> &mut Argument {
         let argument = Argument::new(name, required, default);
         self.arguments.push(argument);
+        self.param_order.push(ParamRef::Arg(self.arguments.len() - 1));
+        self.arguments.last_mut().unwrap()
     }
 
-    fn make_formatter(&self) -> HelpFormatter {
-        HelpFormatter::new(80, 2)
+    pub fn get_name(&self) -> &str {
+        self.name.as_slice()
     }
 
-    fn format_usage(&self, formatter: &mut HelpFormatter) {
+    pub fn get_help_text(&self) -> &str {
+        self.help.as_slice()
+    }
+
+    pub fn get_epilog(&self) -> &str {
+        self.epilog.as_slice()
+    }
+
+    pub fn get_arguments(&self) -> &Vec<Argument> {
+        &self.arguments
+    }
+
+    pub fn get_commands(&self) -> &Vec<Command> {
+        &self.commands
+    }
+
+    pub fn has_commands(&self) -> bool {
+        !self.commands.is_empty()
+    }
+
+    /// Returns the width of the terminal.  A width set on the command comes
+    /// first, then `COLUMNS` overrides the detected size and 80 is used when
+    /// stdout isn't a terminal.
+    fn get_terminal_width(&self) -> usize {
+        match self.terminal_width.or_else(|| parse_columns(env::var("COLUMNS").ok())) {
+            Some(width) => return width,
+            None => (),
+        }
+        if isatty() {
+            match get_terminal_size() {
+                Ok((width, _)) if width > 0 => return width as usize,
+                _ => (),
+            }
+        }
+        80
+    }
+
+    /// Creates the formatter for help output, the theme is only used if
+    /// `styled` is set.
+    fn make_formatter(&self, styled: bool) -> HelpFormatter {
+        let width = min(self.get_terminal_width(), self.max_content_width);
+        let mut formatter = HelpFormatter::new(max(width, 52) - 2, 2);
+        if styled {
+            formatter.theme = self.theme;
+        }
+        formatter
+    }
+
+    /// Returns the pieces of the usage line following the command name.
+    pub fn get_usage_pieces(&self) -> Vec<String> {
         let mut pieces: Vec<String> = Vec::new();
         pieces.push("[OPTIONS]".to_string());
         for argument in self.arguments.iter() {
-            pieces.push(argument.get_usage_piece());
+            if !argument.is_hidden() {
+                pieces.push(argument.get_usage_piece());
+            }
+        }
+        if !self.commands.is_empty() {
+            pieces.push("COMMAND [ARGS]...".to_string());
         }
-        formatter.write_usage(self.name.as_slice(), pieces.concat(), "Usage: ")
+        pieces
+    }
+
+    fn format_usage(&self, formatter: &mut HelpFormatter) {
+        let pieces = self.get_usage_pieces();
+        formatter.write_usage(self.name.as_slice(), pieces.connect(" "), "Usage: ")
     }
 
     pub fn get_usage(&self) -> String {
-        let mut formatter = self.make_formatter();
+        let mut formatter = self.make_formatter(false);
         self.format_usage(&mut formatter);
         formatter.getvalue()
     }
@@ -82,14 +418,202 @@ impl Command {
         }
     }
 
-    fn format_options(&self, formatter: &mut HelpFormatter) {
+    /// Writes the options of one group, `None` is the default "Options"
+    /// section.  With a context, the effective defaults and where they come
+    /// from are shown.
+    fn format_options(&self, formatter: &mut HelpFormatter, group: Option<&str>,
+                      ctx: Option<&Context>) {
         let mut opts: Vec<(String, String)> = Vec::new();
         for option in self.get_options().iter() {
-            opts.push(option.get_help_record());
+            if option.get_group() != group || option.is_hidden() {
+                continue;
+            }
+            // Dynamic defaults are not evaluated for help, their help
+            // string is shown instead.
+            let default = match ctx.and_then(|ctx| self.lookup_external_default(ctx, option)) {
+                Some((ref values, source)) => {
+                    Some(format!("{} (from {})", values.connect(", "), source.describe()))
+                },
+                None => option.get_default_display(),
+            };
+            opts.push(option.get_styled_help_record(&formatter.theme, default));
         }
         if !opts.is_empty() {
-            formatter.enter_section("Options");
-            formatter.write_dl(opts);
+            formatter.enter_section(group.unwrap_or("Options"));
+            formatter.write_dl(opts, 30, 2);
+            formatter.exit_section();
+        }
+    }
+
+    /// Returns how a parameter is shown in messages, eg. `--verbose`.
+    fn get_param_display(&self, name: &str) -> String {
+        match self.options.iter().find(|option| option.get_name() == name) {
+            Some(option) => option.get_display_name(),
+            None => name.to_string(),
+        }
+    }
+
+    fn join_params(&self, names: &[&str], last_sep: &str) -> String {
+        let names: Vec<String> = names.iter().map(|name| self.get_param_display(*name)).collect();
+        match names.len() {
+            0 => String::new(),
+            1 => names[0].clone(),
+            n => format!("{} {} {}", names[..n - 1].connect(", "), last_sep, names[n - 1]),
+        }
+    }
+
+    fn describe_constraint(&self, constraint: &Constraint) -> String {
+        match *constraint {
+            Constraint::MutuallyExclusive(ref names) => {
+                format!("{} are mutually exclusive.", self.join_params(names.as_slice(), "and"))
+            },
+            Constraint::AllOrNone(ref names) => {
+                format!("{} must be given together.", self.join_params(names.as_slice(), "and"))
+            },
+            Constraint::AtLeastOne(ref names) => {
+                format!("At least one of {} is required.", self.join_params(names.as_slice(), "or"))
+            },
+            Constraint::Requires(name, other) => {
+                format!("{} requires {}.", self.get_param_display(name),
+                        self.get_param_display(other))
+            },
+        }
+    }
+
+    fn format_constraints(&self, formatter: &mut HelpFormatter) {
+        if !self.show_constraints || self.constraints.is_empty() {
+            return;
+        }
+        let lines: Vec<String> = self.constraints.iter().map(|constraint| {
+            self.describe_constraint(constraint)
+        }).collect();
+        formatter.enter_section("Constraints");
+        formatter.write_text(format!("\x08\n{}", lines.connect("\n")));
+        formatter.exit_section();
+    }
+
+    /// Validates the constraints against the parameters given on the
+    /// command line, in env vars or in the config file, returns the first
+    /// violation.
+    fn check_constraints(&self, given: &Vec<String>) -> Result<(), String> {
+        let is_given = |name: &&str| given.iter().any(|given| given.as_slice() == *name);
+        for constraint in self.constraints.iter() {
+            match *constraint {
+                Constraint::MutuallyExclusive(ref names) => {
+                    let present: Vec<&str> = names.iter().map(|name| *name)
+                                                  .filter(|name| is_given(name)).collect();
+                    if present.len() > 1 {
+                        return Err(format!("{} are mutually exclusive.",
+                                           self.join_params(present.as_slice(), "and")));
+                    }
+                },
+                Constraint::AllOrNone(ref names) => {
+                    let missing: Vec<&str> = names.iter().map(|name| *name)
+                                                  .filter(|name| !is_given(name)).collect();
+                    if !missing.is_empty() && missing.len() < names.len() {
+                        return Err(format!("{} must be given together, missing {}.",
+                                           self.join_params(names.as_slice(), "and"),
+                                           self.join_params(missing.as_slice(), "and")));
+                    }
+                },
+                Constraint::AtLeastOne(ref names) => {
+                    if !names.iter().any(|name| is_given(name)) {
+                        return Err(self.describe_constraint(constraint));
+                    }
+                },
+                Constraint::Requires(name, other) => {
+                    if is_given(&name) && !is_given(&other) {
+                        return Err(self.describe_constraint(constraint));
+                    }
+                },
+            }
+        }
+        Ok(())
+    }
+
+    fn format_extra_section(&self, formatter: &mut HelpFormatter, title: &str) {
+        for &(ref name, ref text) in self.sections.iter() {
+            if name.as_slice() == title {
+                formatter.enter_section(title);
+                formatter.write_text(text.clone());
+                formatter.exit_section();
+            }
+        }
+    }
+
+    /// Returns the section names of the help page in display order.  The
+    /// default order is option groups, "Options", "Commands" and then the
+    /// extra sections, sections named in `section_order` come first.
+    fn get_section_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for option in self.options.iter() {
+            match option.get_group() {
+                Some(group) if !option.is_hidden() &&
+                               !names.iter().any(|name| name.as_slice() == group) => {
+                    names.push(group.to_string());
+                },
+                _ => (),
+            }
+        }
+        names.push("Options".to_string());
+        names.push("Commands".to_string());
+        names.push("Constraints".to_string());
+        for &(ref name, _) in self.sections.iter() {
+            if !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+
+        let mut ordered: Vec<String> = Vec::new();
+        for name in self.section_order.iter() {
+            if names.contains(name) && !ordered.contains(name) {
+                ordered.push(name.clone());
+            }
+        }
+        for name in names.into_iter() {
+            if !ordered.contains(&name) {
+                ordered.push(name);
+            }
+        }
+        ordered
+    }
+
+    /// Returns the short help used on the command listing, falls back to the
+    /// first sentence of the help message.
+    pub fn get_short_help(&self) -> String {
+        if !self.short_help.is_empty() {
+            return self.short_help.clone();
+        }
+        match self.help.as_slice().split('.').next() {
+            Some(sentence) => sentence.trim().to_string(),
+            None => String::new(),
+        }
+    }
+
+    /// Returns the name shown on the command listing, with aliases if any.
+    fn get_display_name(&self) -> String {
+        if self.aliases.is_empty() {
+            return self.name.clone();
+        }
+        format!("{} ({})", self.name, self.aliases.connect(", "))
+    }
+
+    fn format_commands(&self, formatter: &mut HelpFormatter) {
+        let mut rows: Vec<(String, String)> = Vec::new();
+        for command in self.commands.iter() {
+            if command.hidden {
+                continue;
+            }
+            let name = formatter.theme.style_option(command.get_display_name().as_slice());
+            let mut short_help = command.get_short_help();
+            if command.deprecated {
+                short_help = format!("(Deprecated) {}", short_help).trim_right().to_string();
+            }
+            rows.push((name, short_help));
+        }
+        if !rows.is_empty() {
+            formatter.enter_section("Commands");
+            formatter.write_dl(rows, 30, 2);
             formatter.exit_section();
         }
     }
@@ -103,59 +627,497 @@ impl Command {
         }
     }
 
-    fn format_help(&self, formatter: &mut HelpFormatter) {
+    fn format_help(&self, formatter: &mut HelpFormatter, ctx: Option<&Context>) {
         self.format_usage(formatter);
         self.format_help_text(formatter);
-        self.format_options(formatter);
+        for name in self.get_section_names().iter() {
+            match name.as_slice() {
+                "Options" => self.format_options(formatter, None, ctx),
+                "Commands" => self.format_commands(formatter),
+                "Constraints" => self.format_constraints(formatter),
+                _ => {
+                    self.format_options(formatter, Some(name.as_slice()), ctx);
+                    self.format_extra_section(formatter, name.as_slice());
+                },
+            }
+        }
         self.format_epilog(formatter);
     }
 
+    /// Returns the help page without styles.
     pub fn get_help(&self) -> String {
-        let mut formatter = self.make_formatter();
-        self.format_help(&mut formatter);
+        let mut formatter = self.make_formatter(false);
+        self.format_help(&mut formatter, None);
+        formatter.getvalue()
+    }
+
+    /// Returns the help page with the effective defaults of an invocation,
+    /// styled by the theme if stdout is a terminal.
+    fn get_help_in(&self, ctx: &Context) -> String {
+        let mut formatter = self.make_formatter(isatty());
+        self.format_help(&mut formatter, Some(ctx));
         formatter.getvalue()
     }
 
     /// Returns the help option.
     fn get_help_option(&self) -> Options {
         let help_option_names = vec!["h", "help"];
-        let show_help = |params: Params| {
-            print!("{}", self.get_help());
-        };
         return Options::new(help_option_names[0], help_option_names[1],
                             "Show this message and exit.", true, true, false, false, None);
     }
 
-    /// This invokes the command with given arguments.
-    pub fn invoke(&self, pragram_name: String, args: Vec<String>) {
-        let args = self.parse_args(args);
+    /// Resolves a subcommand by its name or one of its aliases.  If prefix
+    /// matching is enabled, any unambiguous prefix is accepted as well.
+    pub fn resolve_command(&self, name: &str) -> Result<&Command, String> {
+        self.resolve_command_normalized(name, None)
+    }
+
+    /// Like `resolve_command`, but the name and the command names are
+    /// normalized by the func before matching.
+    fn resolve_command_normalized(&self, name: &str,
+                                  func: Option<TokenNormalizeFunc>) -> Result<&Command, String> {
+        let normalized = normalize_token(func, name);
+        let matches_name = |command_name: &str, prefix: bool| {
+            let command_name = normalize_token(func, command_name);
+            if prefix {
+                command_name.starts_with(normalized.as_slice())
+            } else {
+                command_name == normalized
+            }
+        };
+        for command in self.commands.iter() {
+            if matches_name(command.name.as_slice(), false) ||
+               command.aliases.iter().any(|alias| matches_name(alias.as_slice(), false)) {
+                return Ok(command);
+            }
+        }
+        if self.allow_prefix_match {
+            // Hidden commands only resolve by their exact name.
+            let matches: Vec<&Command> = self.commands.iter().filter(|command| {
+                !command.hidden
+            }).filter(|command| {
+                matches_name(command.name.as_slice(), true) ||
+                command.aliases.iter().any(|alias| matches_name(alias.as_slice(), true))
+            }).collect();
+            if matches.len() == 1 {
+                return Ok(matches[0]);
+            } else if matches.len() > 1 {
+                let mut candidates: Vec<String> = matches.iter().map(|command| {
+                    command.name.clone()
+                }).collect();
+                candidates.sort();
+                return Err(format!("Too many matches for \"{}\": {}", name,
+                                   candidates.connect(", ")));
+            }
+        }
+        let mut possibilities: Vec<String> = Vec::new();
+        for command in self.commands.iter().filter(|command| !command.hidden) {
+            possibilities.push(command.name.clone());
+            possibilities.push_all(command.aliases.as_slice());
+        }
+        let matches = get_close_matches(name, &possibilities);
+        match matches.len() {
+            0 => Err(format!("No such command \"{}\".", name)),
+            1 => Err(format!("No such command \"{}\".  Did you mean \"{}\"?", name, matches[0])),
+            _ => Err(format!("No such command \"{}\".  (Possible commands: {})", name,
+                             matches.connect(", "))),
+        }
+    }
+
+    /// Prints a deprecation warning to stderr.
+    fn warn_deprecated(&self, kind: &str, name: &str, hint: Option<&str>) {
+        let mut message = format!("DeprecationWarning: The {} \"{}\" is deprecated.", kind, name);
+        match hint {
+            Some(hint) => message.push_str(format!(" Use \"{}\" instead.", hint).as_slice()),
+            None => (),
+        }
+        message.push_str("\n");
+        old_io::stderr().write_str(message.as_slice()).unwrap();
+    }
+
+    /// Returns the usage and the error message, this is what `run` prints
+    /// to stderr before it exits.
+    fn usage_error(&self, message: &str) -> String {
+        format!("{}\nError: {}\n", self.get_usage(), message)
+    }
+
+    /// This invokes the command with given arguments.  A usage error is
+    /// returned with the usage of the failing command.
+    pub fn invoke(&self, pragram_name: String, args: Vec<String>) -> Result<(), String> {
+        let ctx = Context::new(pragram_name.as_slice());
+        self.invoke_in(ctx, args)
+    }
+
+    /// This invokes the command with given arguments, the default map
+    /// overrides the static defaults of this command and its subcommands.
+    pub fn invoke_with_defaults(&self, program_name: String, args: Vec<String>,
+                                default_map: DefaultMap) -> Result<(), String> {
+        let mut ctx = Context::new(program_name.as_slice());
+        ctx.default_map = Some(default_map);
+        self.invoke_in(ctx, args)
+    }
+
+    /// Invokes the command in the given context, then its subcommand if any.
+    fn invoke_in(&self, mut ctx: Context, args: Vec<String>) -> Result<(), String> {
+        if self.token_normalize_func.is_some() {
+            ctx.token_normalize_func = self.token_normalize_func;
+        }
+        let mut args = match try!(self.parse_args(&mut ctx, args)) {
+            Some(args) => args,
+            None => return Ok(()),
+        };
         let callback = self.callback;
-        callback(args);
+        if self.commands.is_empty() {
+            callback(ctx.params);
+            return Ok(());
+        }
+        if args.is_empty() {
+            print!("{}", self.get_help_in(&ctx));
+            return Ok(());
+        }
+        let command_name = args.remove(0);
+        let command = match self.resolve_command_normalized(command_name.as_slice(),
+                                                            ctx.token_normalize_func) {
+            Ok(command) => command,
+            Err(message) => return Err(self.usage_error(message.as_slice())),
+        };
+        callback(ctx.params.clone());
+        if command.deprecated {
+            self.warn_deprecated("command", command.name.as_slice(),
+                                 command.deprecated_hint.as_ref().map(|hint| hint.as_slice()));
+        }
+        command.invoke_in(ctx.child(command.name.as_slice()), args)
     }
 
     /// Get all options plus help option.
-    fn get_options(&self) -> Vec<Options> {
+    pub fn get_options(&self) -> Vec<Options> {
         let mut options: Vec<Options> = Vec::new();
         for option in self.options.iter() {
             options.push(option.clone());
         }
+        if self.config_app_name.is_some() {
+            options.push(Options::new("", "config", "Read option defaults from this file.",
+                                      false, false, false, false, None));
+        }
         let help_option = self.get_help_option();
         options.push(help_option);
         return options;
     }
 
     /// Creates the underlying option parser for this command.
-    fn make_parser(&self) -> getopts::Options {
-        let mut parser = getopts::Options::new();
+    fn make_parser(&self) -> OptionParser {
+        let mut parser = OptionParser::new();
         for option in self.get_options().iter() {
             option.add_to_parser(&mut parser);
         }
+        if self.dump_schema_option {
+            let mut option = Options::new("", "dump-schema", "", true, true,
+                                          false, false, None);
+            option.hidden(true);
+            parser.add_option(option);
+        }
+        // Everything after the subcommand name belongs to the subcommand,
+        // unless the command says otherwise.
+        parser.allow_interspersed_args = self.allow_interspersed_args
+                                             .unwrap_or(self.commands.is_empty());
+        parser.negative_numbers = self.arguments.iter().any(|argument| argument.is_number());
+        parser.ignore_unknown_options = self.ignore_unknown_options;
         return parser;
     }
 
-    /// Create the parser and parses the arguments.
-    fn parse_args(&self, args: Vec<String>) -> Vec<String> {
-        args
+    /// Loads the config file given on the command line, by the env var, or
+    /// from the application's config folder.  Only an explicitly given file
+    /// has to exist.
+    fn load_config(&self, app_name: &str, path: Option<String>) -> Result<Option<Config>, String> {
+        let envvar: String = format!("{}_CONFIG", app_name).chars().map(|c| {
+            if c.is_alphanumeric() { c.to_ascii_uppercase() } else { '_' }
+        }).collect();
+        let path = match path.or(env::var(envvar.as_slice()).ok()) {
+            Some(path) => PathBuf::new(path.as_slice()),
+            None => {
+                let path = get_app_dir(app_name).join("config");
+                if !path.exists() {
+                    return Ok(None);
+                }
+                path
+            },
+        };
+        Config::load(&path).map(|config| Some(config))
+    }
+
+    /// Looks up the value of an option that wasn't given on the command line.
+    /// Env vars override the config file, which overrides the default map,
+    /// which overrides the option's own default.  Returns the values and
+    /// where they come from.
+    fn lookup_default(&self, ctx: &Context,
+                      option: &Options) -> Option<(Vec<String>, ParamSource)> {
+        self.lookup_external_default(ctx, option).or_else(|| {
+            option.get_default_value().map(|default| (vec![default], ParamSource::Default))
+        })
+    }
+
+    /// Looks up the value of an option in the env var, the config file and
+    /// the default map.
+    fn lookup_external_default(&self, ctx: &Context,
+                               option: &Options) -> Option<(Vec<String>, ParamSource)> {
+        let name = option.get_name();
+        let mut found: Option<(Vec<String>, ParamSource)> = None;
+        match option.get_envvar() {
+            Some(envvar) => match env::var(envvar) {
+                Ok(ref value) if !value.is_empty() => {
+                    let values = if option.is_multiple() {
+                        value.as_slice().split(|c: char| c.is_whitespace())
+                                        .filter(|value| !value.is_empty())
+                                        .map(|value| value.to_string()).collect()
+                    } else {
+                        vec![value.clone()]
+                    };
+                    found = Some((values, ParamSource::Environment));
+                },
+                _ => (),
+            },
+            None => (),
+        }
+        if found.is_none() {
+            match ctx.config {
+                Some(ref config) => match config.get(ctx.config_section().as_slice(), name) {
+                    Some(values) => found = Some((values, ParamSource::ConfigFile)),
+                    None => (),
+                },
+                None => (),
+            }
+        }
+        if found.is_none() {
+            found = ctx.lookup_default_map(name).map(|values| (values, ParamSource::DefaultMap));
+        }
+        match found {
+            Some((values, source)) => {
+                if option.is_bool_flag() {
+                    // Flags are set by a truthy value and unset otherwise.
+                    let value = values.last().map(|value| value.to_ascii_lowercase());
+                    return match value.as_ref().map(|value| value.as_slice()) {
+                        Some("1") | Some("true") | Some("yes") | Some("on") | Some("y") => {
+                            Some((vec!["true".to_string()], source))
+                        },
+                        _ => Some((Vec::new(), source)),
+                    };
+                }
+                Some((values, source))
+            },
+            None => None,
+        }
+    }
+
+    /// Runs the parameter callback on the values, a returned error is a
+    /// usage error.
+    fn run_param_callback(&self, ctx: &Context, display_name: &str,
+                          callback: Option<ParamCallback>,
+                          values: Vec<String>) -> Result<Vec<String>, String> {
+        match callback {
+            Some(callback) => callback(ctx, values).map_err(|message| {
+                self.usage_error(format!("Invalid value for \"{}\": {}",
+                                         display_name, message).as_slice())
+            }),
+            None => Ok(values),
+        }
+    }
+
+    /// Fails unless the value of a number parameter is a number.
+    fn check_number(&self, display_name: &str, value: &str) -> Result<(), String> {
+        if value.parse::<f64>().is_err() {
+            let message = format!("Invalid value for \"{}\": \"{}\" is not a valid number.",
+                                  display_name, value);
+            return Err(self.usage_error(message.as_slice()));
+        }
+        Ok(())
+    }
+
+    /// Resolves, validates and stores the values of one option.
+    fn process_option(&self, ctx: &mut Context, option: &Options,
+                      cmdline: Option<&Vec<String>>,
+                      given: &mut Vec<String>) -> Result<(), String> {
+        let name = option.get_name();
+        let display_name = option.get_display_name();
+        let found = match cmdline {
+            Some(values) => {
+                if option.is_deprecated() {
+                    self.warn_deprecated("option", display_name.as_slice(),
+                                         option.get_deprecated_hint());
+                }
+                Some((values.clone(), ParamSource::CommandLine))
+            },
+            None => self.lookup_default(ctx, option),
+        };
+        let (values, source) = match found {
+            Some(found) => found,
+            None => {
+                if option.is_required() {
+                    return Err(self.usage_error(format!("Missing option \"{}\".",
+                                                        display_name).as_slice()));
+                }
+                return Ok(());
+            },
+        };
+        // Values from the user's environment and config file count as given
+        // for the constraints, defaults set by the program don't.
+        match source {
+            ParamSource::Default | ParamSource::DefaultMap => (),
+            _ if values.is_empty() => (),
+            _ => given.push(name.to_string()),
+        }
+        let choices = option.get_choices();
+        let mut checked: Vec<String> = Vec::new();
+        for value in values.into_iter() {
+            if option.is_number() {
+                try!(self.check_number(display_name.as_slice(), value.as_slice()));
+            }
+            if choices.is_empty() {
+                checked.push(value);
+                continue;
+            }
+            // Choices are stored as declared, the value may be normalized.
+            let func = ctx.token_normalize_func;
+            let normalized = normalize_token(func, value.as_slice());
+            match choices.iter().find(|choice| normalize_token(func, **choice) == normalized) {
+                Some(choice) => checked.push(choice.to_string()),
+                None => {
+                    let message = format!("Invalid value for \"{}\": \"{}\" is not one of {}.",
+                                          display_name, value, choices.connect(", "));
+                    return Err(self.usage_error(message.as_slice()));
+                },
+            }
+        }
+        let values = try!(self.run_param_callback(ctx, display_name.as_slice(),
+                                                  option.get_callback(), checked));
+        ctx.params.insert_with_source(name, values, source);
+        Ok(())
+    }
+
+    /// Resolves and stores the value of one argument.
+    fn process_argument(&self, ctx: &mut Context, argument: &Argument,
+                        value: Option<String>, given: &mut Vec<String>) -> Result<(), String> {
+        let name = argument.get_name();
+        let (values, source) = match value {
+            Some(value) => {
+                if argument.is_deprecated() {
+                    self.warn_deprecated("argument", name, argument.get_deprecated_hint());
+                }
+                if argument.is_number() {
+                    try!(self.check_number(name, value.as_slice()));
+                }
+                given.push(name.to_string());
+                (vec![value], ParamSource::CommandLine)
+            },
+            None => {
+                let default = ctx.lookup_default_map(name).map(|values| {
+                    (values, ParamSource::DefaultMap)
+                }).or_else(|| {
+                    argument.get_default_value().map(|default| {
+                        (vec![default], ParamSource::Default)
+                    })
+                });
+                match default {
+                    Some(found) => found,
+                    None => {
+                        if argument.is_required() {
+                            return Err(self.usage_error(format!("Missing argument \"{}\".",
+                                                                name).as_slice()));
+                        }
+                        return Ok(());
+                    },
+                }
+            },
+        };
+        let values = try!(self.run_param_callback(ctx, name, argument.get_callback(), values));
+        ctx.params.insert_with_source(name, values, source);
+        Ok(())
+    }
+
+    /// Create the parser and parses the arguments into the params of the
+    /// context.  Returns the leftover arguments, which are the subcommand and
+    /// its arguments if this command has subcommands, or `None` if the help
+    /// page or the schema was printed instead.
+    fn parse_args(&self, ctx: &mut Context,
+                  args: Vec<String>) -> Result<Option<Vec<String>>, String> {
+        let mut parser = self.make_parser();
+        parser.token_normalize_func = ctx.token_normalize_func;
+        let result = match parser.parse_args(args) {
+            Ok(result) => result,
+            Err(message) => return Err(self.usage_error(message.as_slice())),
+        };
+        match self.config_app_name {
+            Some(ref app_name) => {
+                let path = result.opts.get("config").and_then(|values| values.last().cloned());
+                match self.load_config(app_name.as_slice(), path) {
+                    Ok(config) => ctx.config = config,
+                    // A broken config file shouldn't keep users from the help page.
+                    Err(_) if result.opts.contains_key("help") => (),
+                    Err(message) => return Err(self.usage_error(message.as_slice())),
+                }
+            },
+            None => (),
+        }
+        if result.opts.contains_key("help") {
+            print!("{}", self.get_help_in(ctx));
+            return Ok(None);
+        }
+        if result.opts.contains_key("dump-schema") {
+            println!("{}", self.get_schema());
+            return Ok(None);
+        }
+
+        // Arguments take the positional values in declaration order.
+        let mut largs = result.largs.into_iter();
+        let mut argument_values: Vec<Option<String>> = Vec::new();
+        for _ in self.arguments.iter() {
+            argument_values.push(largs.next());
+        }
+
+        // Eager parameters are processed first, then the rest in
+        // declaration order.
+        let mut order: Vec<ParamRef> = Vec::new();
+        for param in self.param_order.iter() {
+            match *param {
+                ParamRef::Opt(index) if self.options[index].is_eager() => order.push(*param),
+                _ => (),
+            }
+        }
+        for param in self.param_order.iter() {
+            if !order.contains(param) {
+                order.push(*param);
+            }
+        }
+
+        let mut given: Vec<String> = Vec::new();
+        for param in order.into_iter() {
+            match param {
+                ParamRef::Opt(index) => {
+                    let option = &self.options[index];
+                    try!(self.process_option(ctx, option, result.opts.get(option.get_name()),
+                                             &mut given));
+                },
+                ParamRef::Arg(index) => {
+                    let argument = &self.arguments[index];
+                    try!(self.process_argument(ctx, argument, argument_values[index].take(),
+                                               &mut given));
+                },
+            }
+        }
+        match self.check_constraints(&given) {
+            Ok(()) => (),
+            Err(message) => return Err(self.usage_error(message.as_slice())),
+        }
+        let rest: Vec<String> = largs.collect();
+        if self.commands.is_empty() && self.allow_extra_args {
+            ctx.extra_args = rest;
+            ctx.params.set_extra_args(ctx.extra_args.clone());
+            return Ok(Some(Vec::new()));
+        }
+        if self.commands.is_empty() && !rest.is_empty() {
+            return Err(self.usage_error(format!("Got unexpected extra argument(s) ({})",
+                                                rest.connect(" ")).as_slice()));
+        }
+        Ok(Some(rest))
     }
 
     /// This is the way to run one command application.
@@ -164,8 +1126,40 @@ impl Command {
         let program = args.remove(0);
         let program_path = Path::new(program.as_slice());
         let program_name = program_path.file_name().unwrap().to_str().unwrap();
-        // Hook for the Bash completion.
-        // bashcomplete(self, program_name);
-        self.invoke(program_name.to_string(), args);
+        // Hook for the shell completion.
+        if bashcomplete(self, program_name) {
+            return;
+        }
+        if self.response_files {
+            args = match expand_response_files(args) {
+                Ok(args) => args,
+                Err(message) => self.exit_with_error(self.usage_error(message.as_slice())),
+            };
+        }
+        match self.invoke(program_name.to_string(), args) {
+            Ok(()) => (),
+            Err(message) => self.exit_with_error(message),
+        }
+    }
+
+    /// Prints the usage error to stderr and exits.
+    fn exit_with_error(&self, message: String) -> ! {
+        old_io::stderr().write_str(message.as_slice()).unwrap();
+        process::exit(2);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::parse_columns;
+
+    #[test]
+    fn test_parse_columns() {
+        assert_eq!(parse_columns(Some("120".to_string())), Some(120));
+        assert_eq!(parse_columns(Some(" 100\n".to_string())), Some(100));
+        assert_eq!(parse_columns(Some("0".to_string())), None);
+        assert_eq!(parse_columns(Some("wide".to_string())), None);
+        assert_eq!(parse_columns(None), None);
     }
 }
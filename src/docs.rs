@@ -0,0 +1,191 @@
+// This module implements reference documentation export.
+// Copyright (c) 2015 by Shipeng Feng.
+// Licensed under the BSD License, see LICENSE for more details.
+
+use std::cmp::min;
+use std::slice::SliceConcatExt;
+
+use core::Command;
+use types::Argument;
+
+
+fn escape_markdown_cell(text: &str) -> String {
+    text.replace("|", "\\|").replace("\n", " ")
+}
+
+
+fn escape_html(text: &str) -> String {
+    text.replace("&", "&amp;").replace("<", "&lt;").replace(">", "&gt;")
+        .replace("\"", "&quot;")
+}
+
+
+/// Returns the rows of the options table: names, help, type, default, env var
+/// and required.
+fn get_option_rows(command: &Command) -> Vec<(String, String, String, String, String, String)> {
+    let mut rows = Vec::new();
+    for option in command.get_options().iter().filter(|option| !option.is_hidden()) {
+        let default = option.get_default_display().unwrap_or(String::new());
+        let envvar = option.get_envvar().unwrap_or("").to_string();
+        let required = if option.is_required() { "yes" } else { "no" };
+        rows.push((option.get_opts().connect(", "), option.get_help().to_string(),
+                   option.get_type_name().to_string(), default, envvar, required.to_string()));
+    }
+    rows
+}
+
+
+fn get_visible_arguments(command: &Command) -> Vec<&Argument> {
+    command.get_arguments().iter().filter(|argument| !argument.is_hidden()).collect()
+}
+
+
+/// Describes an argument as required or optional, with its default if any.
+fn describe_argument(argument: &Argument) -> String {
+    let required = if argument.is_required() { "required" } else { "optional" };
+    match argument.get_default_display() {
+        Some(default) => format!("{}, default: {}", required, default),
+        None => required.to_string(),
+    }
+}
+
+
+fn write_markdown(command: &Command, path: &Vec<String>, level: usize, out: &mut Vec<String>) {
+    let anchor = path.connect("-");
+    let heading: String = range(0, level).map(|_| '#').collect();
+    out.push(format!("<a name=\"{}\"></a>", anchor));
+    out.push(format!("{} {}\n", heading, path.connect(" ")));
+
+    out.push(format!("```\n{} {}\n```\n", path.connect(" "),
+                     command.get_usage_pieces().connect(" ")));
+    if !command.get_help_text().is_empty() {
+        out.push(format!("{}\n", command.get_help_text()));
+    }
+
+    let arguments = get_visible_arguments(command);
+    if !arguments.is_empty() {
+        out.push("**Arguments**\n".to_string());
+        for argument in arguments.into_iter() {
+            out.push(format!("- `{}` ({})", argument.get_name(),
+                             describe_argument(argument)));
+        }
+        out.push(String::new());
+    }
+
+    out.push("**Options**\n".to_string());
+    out.push("| Option | Description | Type | Default | Env var | Required |".to_string());
+    out.push("| ------ | ----------- | ---- | ------- | ------- | -------- |".to_string());
+    for (opts, help, type_name, default, envvar, required) in get_option_rows(command).into_iter() {
+        out.push(format!("| `{}` | {} | {} | {} | {} | {} |", opts,
+                         escape_markdown_cell(help.as_slice()), type_name,
+                         escape_markdown_cell(default.as_slice()), envvar, required));
+    }
+    out.push(String::new());
+
+    if command.has_commands() {
+        out.push("**Commands**\n".to_string());
+        for subcommand in command.get_commands().iter().filter(|command| !command.is_hidden()) {
+            out.push(format!("- [{}](#{}-{}): {}", subcommand.get_name(), anchor,
+                             subcommand.get_name(), subcommand.get_short_help()));
+        }
+        out.push(String::new());
+    }
+
+    if !command.get_epilog().is_empty() {
+        out.push(format!("{}\n", command.get_epilog()));
+    }
+
+    for subcommand in command.get_commands().iter().filter(|command| !command.is_hidden()) {
+        let mut subpath = path.clone();
+        subpath.push(subcommand.get_name().to_string());
+        write_markdown(subcommand, &subpath, min(level + 1, 6), out);
+    }
+}
+
+
+/// Renders the reference documentation of a command and all its
+/// subcommands as Markdown.
+pub fn to_markdown(command: &Command) -> String {
+    let mut out: Vec<String> = Vec::new();
+    write_markdown(command, &vec![command.get_name().to_string()], 1, &mut out);
+    out.connect("\n")
+}
+
+
+fn write_html(command: &Command, path: &Vec<String>, level: usize, out: &mut Vec<String>) {
+    let anchor = path.connect("-");
+    out.push(format!("<section id=\"{}\">", anchor));
+    out.push(format!("<h{0}>{1}</h{0}>", level, escape_html(path.connect(" ").as_slice())));
+    out.push(format!("<pre>{} {}</pre>", escape_html(path.connect(" ").as_slice()),
+                     escape_html(command.get_usage_pieces().connect(" ").as_slice())));
+    if !command.get_help_text().is_empty() {
+        out.push(format!("<p>{}</p>", escape_html(command.get_help_text())));
+    }
+
+    let arguments = get_visible_arguments(command);
+    if !arguments.is_empty() {
+        out.push("<h4>Arguments</h4>".to_string());
+        out.push("<ul>".to_string());
+        for argument in arguments.into_iter() {
+            out.push(format!("<li><code>{}</code> ({})</li>",
+                             escape_html(argument.get_name()),
+                             escape_html(describe_argument(argument).as_slice())));
+        }
+        out.push("</ul>".to_string());
+    }
+
+    out.push("<h4>Options</h4>".to_string());
+    out.push("<table>".to_string());
+    out.push("<tr><th>Option</th><th>Description</th><th>Type</th>\
+              <th>Default</th><th>Env var</th><th>Required</th></tr>".to_string());
+    for (opts, help, type_name, default, envvar, required) in get_option_rows(command).into_iter() {
+        out.push(format!("<tr><td><code>{}</code></td><td>{}</td><td>{}</td>\
+                          <td>{}</td><td>{}</td><td>{}</td></tr>",
+                         escape_html(opts.as_slice()), escape_html(help.as_slice()),
+                         type_name, escape_html(default.as_slice()),
+                         escape_html(envvar.as_slice()), required));
+    }
+    out.push("</table>".to_string());
+
+    if command.has_commands() {
+        out.push("<h4>Commands</h4>".to_string());
+        out.push("<ul>".to_string());
+        for subcommand in command.get_commands().iter().filter(|command| !command.is_hidden()) {
+            out.push(format!("<li><a href=\"#{}-{}\">{}</a>: {}</li>", anchor,
+                             escape_html(subcommand.get_name()),
+                             escape_html(subcommand.get_name()),
+                             escape_html(subcommand.get_short_help().as_slice())));
+        }
+        out.push("</ul>".to_string());
+    }
+
+    if !command.get_epilog().is_empty() {
+        out.push(format!("<p>{}</p>", escape_html(command.get_epilog())));
+    }
+    out.push("</section>".to_string());
+
+    for subcommand in command.get_commands().iter().filter(|command| !command.is_hidden()) {
+        let mut subpath = path.clone();
+        subpath.push(subcommand.get_name().to_string());
+        write_html(subcommand, &subpath, min(level + 1, 6), out);
+    }
+}
+
+
+/// Renders the reference documentation of a command and all its
+/// subcommands as one standalone HTML page.
+pub fn to_html(command: &Command) -> String {
+    let mut out: Vec<String> = Vec::new();
+    out.push("<!doctype html>".to_string());
+    out.push("<html>".to_string());
+    out.push("<head>".to_string());
+    out.push("<meta charset=\"utf-8\">".to_string());
+    out.push(format!("<title>{}</title>", escape_html(command.get_name())));
+    out.push("</head>".to_string());
+    out.push("<body>".to_string());
+    write_html(command, &vec![command.get_name().to_string()], 1, &mut out);
+    out.push("</body>".to_string());
+    out.push("</html>".to_string());
+    out.push(String::new());
+    out.connect("\n")
+}
@@ -3,9 +3,93 @@
 // Licensed under the BSD License, see LICENSE for more details.
 
 use std;
-use std::cmp::max;
+use std::cmp::{min, max};
 use std::slice::SliceConcatExt;
 
+use utils::display_width;
+use term::{Style, Color, Cyan, Yellow};
+
+
+/// The styles used for help pages.  Examples:
+///
+/// ```rust,no_run
+/// use cli::{Command, Params, Theme, Green};
+///
+/// fn hello(_: Params) {}
+///
+/// let mut command = Command::new("hello", hello);
+/// let mut theme = Theme::new();
+/// theme.option_fg = Some(Green);
+/// command.theme(theme);
+/// ```
+///
+#[derive(Clone, Copy)]
+pub struct Theme {
+    pub heading_bold: bool,  // show section headings in bold
+    pub heading_fg: Option<Color>,  // the color of section headings
+    pub option_fg: Option<Color>,  // the color of option names
+    pub metavar_dim: bool,  // show metavars dimmed
+    pub default_fg: Option<Color>,  // the color of default values
+}
+
+impl Theme {
+    /// Create the default theme.
+    pub fn new() -> Theme {
+        Theme {
+            heading_bold: true,
+            heading_fg: None,
+            option_fg: Some(Cyan),
+            metavar_dim: true,
+            default_fg: Some(Yellow),
+        }
+    }
+
+    /// Create one theme without any styles.
+    pub fn plain() -> Theme {
+        Theme {
+            heading_bold: false,
+            heading_fg: None,
+            option_fg: None,
+            metavar_dim: false,
+            default_fg: None,
+        }
+    }
+
+    fn apply(text: &str, fg: Option<Color>, bold: bool, dim: bool) -> String {
+        if fg.is_none() && !bold && !dim {
+            return text.to_string();
+        }
+        let mut styled = Style::new(text.to_string());
+        match fg {
+            Some(color) => styled.fg(color),
+            None => (),
+        }
+        if bold {
+            styled.bold(true);
+        }
+        if dim {
+            styled.dim(true);
+        }
+        format!("{}", styled)
+    }
+
+    pub fn style_heading(&self, text: &str) -> String {
+        Theme::apply(text, self.heading_fg, self.heading_bold, false)
+    }
+
+    pub fn style_option(&self, text: &str) -> String {
+        Theme::apply(text, self.option_fg, false, false)
+    }
+
+    pub fn style_metavar(&self, text: &str) -> String {
+        Theme::apply(text, None, false, self.metavar_dim)
+    }
+
+    pub fn style_default(&self, text: &str) -> String {
+        Theme::apply(text, self.default_fg, false, false)
+    }
+}
+
 
 /// This helps with formatting text-based help pages.
 pub struct HelpFormatter {
@@ -13,6 +97,7 @@ pub struct HelpFormatter {
     indent_increment: usize,
     current_indent: usize,
     buffer: Vec<String>,
+    pub theme: Theme,
 }
 
 impl HelpFormatter {
@@ -27,6 +112,7 @@ impl HelpFormatter {
             indent_increment: indent_increment,
             current_indent: 0,
             buffer: Vec::new(),
+            theme: Theme::plain(),
         }
     }
 
@@ -55,16 +141,46 @@ impl HelpFormatter {
     /// Writes a heading.
     pub fn write_heading(&mut self, heading: &str) {
         let current_indent = self.current_indent;
+        let heading = self.theme.style_heading(heading);
         self.write(format!("{:>2$}{}\n", "", heading, current_indent));
     }
 
-    /// Writes a definition list into the buffer.
-    pub fn write_dl(&mut self, rows: Vec<(String, String)>) {
-        let col_spacing: usize = 2;
+    /// Writes a definition list into the buffer.  The first column is at
+    /// most `col_max` wide, longer entries get their own line, and the second
+    /// column is wrapped and aligned after `col_spacing` spaces.
+    pub fn write_dl(&mut self, rows: Vec<(String, String)>, col_max: usize, col_spacing: usize) {
         let current_indent = self.current_indent;
+        let first_width = rows.iter().map(|&(ref first, _)| display_width(first.as_slice()))
+                                     .max().unwrap_or(0);
+        let first_col = min(first_width, col_max) + col_spacing;
+        let align: String = range(0, first_col + current_indent).map(|_| ' ').collect();
         for &(ref first, ref second) in rows.iter() {
             self.write(format!("{:>2$}{}", "", first, current_indent));
-            self.write(format!("{:>2$}{}\n", "", second, col_spacing));
+            if second.is_empty() {
+                self.write(String::from_str("\n"));
+                continue;
+            }
+            let first_len = display_width(first.as_slice());
+            if first_len <= first_col - col_spacing {
+                self.write(format!("{:>1$}", "", first_col - first_len));
+            } else {
+                self.write(String::from_str("\n"));
+                self.write(align.clone());
+            }
+            let text_width = max(self.width - first_col - current_indent, 10);
+            let wrapped = wrap_text(second.clone(), text_width, "", "", true);
+            let mut lines = wrapped.as_slice().lines();
+            match lines.next() {
+                Some(line) => self.write(format!("{}\n", line)),
+                None => self.write(String::from_str("\n")),
+            }
+            for line in lines {
+                if line.is_empty() {
+                    self.write(String::from_str("\n"));
+                } else {
+                    self.write(format!("{}{}\n", align, line));
+                }
+            }
         }
     }
 
@@ -87,21 +203,29 @@ impl HelpFormatter {
         for _ in range(0, self.current_indent) {
             indent.push_str(" ");
         }
-        self.write(wrap_text(text, text_width, indent.as_slice(), indent.as_slice()));
+        self.write(wrap_text(text, text_width, indent.as_slice(), indent.as_slice(), true));
         self.write(String::from_str("\n"));
     }
 
     /// Writes a usage line.
     pub fn write_usage(&mut self, name: &str, args: String, prefix: &str) {
-        let prefix = format!("{:>2$}{}", prefix, name, self.current_indent);
-        let prefix_len = prefix.len();
-        self.write(prefix);
-        let text_width = max(self.width - self.current_indent - prefix_len, 10);
-        let mut indent = String::from_str(" ");
-        for _ in range(0, prefix_len) {
-            indent.push_str(" ");
+        let usage_prefix = format!("{:>2$}{} ", prefix, name, self.current_indent);
+        let prefix_len = display_width(usage_prefix.as_slice());
+        let text_width = max(self.width - self.current_indent, 10);
+        if text_width >= prefix_len + 20 {
+            // The arguments are wrapped next to the prefix.
+            let indent: String = range(0, prefix_len).map(|_| ' ').collect();
+            self.write(wrap_text(args, text_width, usage_prefix.as_slice(),
+                                 indent.as_slice(), false));
+        } else {
+            // The prefix is too long, put the arguments on their own lines.
+            self.write(usage_prefix);
+            self.write(String::from_str("\n"));
+            let indent_len = max(self.current_indent, display_width(prefix)) + 4;
+            let indent: String = range(0, indent_len).map(|_| ' ').collect();
+            self.write(wrap_text(args, text_width, indent.as_slice(),
+                                 indent.as_slice(), false));
         }
-        self.write(wrap_text(args, text_width, " ", indent.as_slice()));
         self.write(String::from_str("\n"));
     }
 
@@ -112,8 +236,100 @@ impl HelpFormatter {
 }
 
 
-/// A helper function that wraps text.
-/// TODO
-fn wrap_text(text: String, width: usize, initial_indent: &str, subsequent_indent: &str) -> String {
-    return text;
+/// Marks a paragraph that should not be rewrapped when it is the first line
+/// of the paragraph.
+const NO_REWRAP_MARKER: &'static str = "\x08";
+
+
+/// Fills the words of a text into lines no longer than `width`, including
+/// the indents.  Words longer than the width are put on their own line.
+fn fill(text: &str, width: usize, initial_indent: &str, subsequent_indent: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut line = initial_indent.to_string();
+    let mut line_len = display_width(initial_indent);
+    let mut line_empty = true;
+    for word in text.split(|c: char| c.is_whitespace()).filter(|word| !word.is_empty()) {
+        let word_len = display_width(word);
+        if !line_empty && line_len + 1 + word_len > width {
+            lines.push(line);
+            line = subsequent_indent.to_string();
+            line_len = display_width(subsequent_indent);
+            line_empty = true;
+        }
+        if !line_empty {
+            line.push(' ');
+            line_len = line_len + 1;
+        }
+        line.push_str(word);
+        line_len = line_len + word_len;
+        line_empty = false;
+    }
+    if !line_empty {
+        lines.push(line);
+    }
+    lines
+}
+
+
+/// Only indents the lines of a text, used for paragraphs that should keep
+/// their literal layout.
+fn indent_only(text: &str, initial_indent: &str, subsequent_indent: &str) -> Vec<String> {
+    text.lines().enumerate().map(|(i, line)| {
+        let indent = if i == 0 { initial_indent } else { subsequent_indent };
+        format!("{}{}", indent, line)
+    }).collect()
+}
+
+
+/// A helper function that wraps text.  If `preserve_paragraphs` is set,
+/// paragraphs are separated by blank lines and wrapped one by one, keeping
+/// the indentation of indented blocks.  A paragraph whose first line only
+/// contains `\x08` is not rewrapped at all.
+fn wrap_text(text: String, width: usize, initial_indent: &str, subsequent_indent: &str,
+             preserve_paragraphs: bool) -> String {
+    let text = text.replace("\t", "        ");
+    if !preserve_paragraphs {
+        return fill(text.as_slice(), width, initial_indent, subsequent_indent).connect("\n");
+    }
+
+    // (extra indent, keep layout, text) for each paragraph.
+    let mut paragraphs: Vec<(usize, bool, String)> = Vec::new();
+    let mut buf: Vec<&str> = Vec::new();
+    let mut indent: Option<usize> = None;
+    for line in text.as_slice().lines().chain(Some("").into_iter()) {
+        if line.trim().is_empty() {
+            if !buf.is_empty() {
+                let extra = indent.unwrap_or(0);
+                if buf[0].trim() == NO_REWRAP_MARKER {
+                    paragraphs.push((extra, true, buf[1..].connect("\n")));
+                } else {
+                    paragraphs.push((extra, false, buf.connect(" ")));
+                }
+                buf.clear();
+            }
+            indent = None;
+        } else {
+            let stripped = line.trim_left();
+            if indent.is_none() {
+                indent = Some(line.chars().count() - stripped.chars().count());
+                buf.push(stripped);
+            } else {
+                buf.push(line);
+            }
+        }
+    }
+
+    let mut rv: Vec<String> = Vec::new();
+    for &(extra, keep_layout, ref paragraph) in paragraphs.iter() {
+        let extra_indent: String = range(0, extra).map(|_| ' ').collect();
+        let initial = format!("{}{}", initial_indent, extra_indent);
+        let subsequent = format!("{}{}", subsequent_indent, extra_indent);
+        let lines = if keep_layout {
+            indent_only(paragraph.as_slice(), initial.as_slice(), subsequent.as_slice())
+        } else {
+            fill(paragraph.as_slice(), width, initial.as_slice(), subsequent.as_slice())
+        };
+        rv.push(lines.connect("\n"));
+    }
+    rv.connect("\n\n")
 }
@@ -13,10 +13,18 @@
 
 extern crate libc;
 extern crate time;
-extern crate getopts;
 
 /* public api */
-pub use utils::sprintln;
+pub use core::{Command, Context};
+pub use types::{Params, CommandCallback, CompletionCallback, DefaultCallback, ParamCallback};
+pub use types::{Options, Argument, Constraint, ParamSource, TokenNormalizeFunc};
+pub use completion::CompletionItem;
+pub use manpage::ManPage;
+pub use formatting::Theme;
+pub use config::{Config, DefaultMap, get_app_dir};
+pub use docs::{to_markdown, to_html};
+pub use utils::{sprintln, display_width};
+pub use parser::{split_args, expand_response_files};
 pub use term::{
     Style,
     Color,
@@ -43,3 +51,9 @@ mod types;
 mod utils;
 mod term;
 mod formatting;
+mod parser;
+mod completion;
+mod manpage;
+mod docs;
+mod schema;
+mod config;
@@ -0,0 +1,154 @@
+// This module implements man page generation.
+// Copyright (c) 2015 by Shipeng Feng.
+// Licensed under the BSD License, see LICENSE for more details.
+
+use std::io;
+use std::ascii::AsciiExt;
+use std::io::Write;
+use std::fs::File;
+use std::path::Path;
+use std::slice::SliceConcatExt;
+
+use time;
+
+use core::Command;
+
+
+/// Escapes text for roff.
+fn escape(text: &str) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        let mut line = line.replace("\\", "\\e").replace("-", "\\-");
+        if line.starts_with(".") || line.starts_with("'") {
+            line = format!("\\&{}", line);
+        }
+        lines.push(line);
+    }
+    lines.connect("\n")
+}
+
+
+/// Generates roff man pages for a command and all its subcommands.  Each
+/// subcommand gets its own page named after the command path, eg.
+/// `tool-remove`.  Examples:
+///
+/// ```rust,no_run
+/// use std::path::Path;
+/// use cli::{Command, ManPage, Params};
+///
+/// fn hello(_: Params) {}
+///
+/// let command = Command::new("hello", hello);
+/// let man = ManPage::new("1.0.0");
+/// man.write_pages(&command, &Path::new("man")).unwrap();
+/// ```
+///
+pub struct ManPage {
+    pub version: String,  // the version shown in the footer
+    pub section: &'static str,  // the manual section
+    pub date: String,  // the date shown in the footer
+}
+
+impl ManPage {
+    /// Create one new man page generator.
+    pub fn new(version: &str) -> ManPage {
+        ManPage {
+            version: version.to_string(),
+            section: "1",
+            date: time::strftime("%Y-%m-%d", &time::now()).unwrap(),
+        }
+    }
+
+    fn render_page(&self, command: &Command, path: &Vec<String>) -> String {
+        let page_name = path.connect("-");
+        let mut out: Vec<String> = Vec::new();
+        out.push(format!(".TH \"{}\" \"{}\" \"{}\" \"{} {}\"", page_name.to_ascii_uppercase(),
+                         self.section, self.date, path[0], self.version));
+
+        out.push(".SH NAME".to_string());
+        let short_help = command.get_short_help();
+        if short_help.is_empty() {
+            out.push(escape(page_name.as_slice()));
+        } else {
+            out.push(format!("{} \\- {}", escape(page_name.as_slice()),
+                             escape(short_help.as_slice())));
+        }
+
+        out.push(".SH SYNOPSIS".to_string());
+        out.push(format!("\\fB{}\\fR {}", escape(path.connect(" ").as_slice()),
+                         escape(command.get_usage_pieces().connect(" ").as_slice())));
+
+        if !command.get_help_text().is_empty() {
+            out.push(".SH DESCRIPTION".to_string());
+            out.push(escape(command.get_help_text()));
+        }
+
+        out.push(".SH OPTIONS".to_string());
+        for option in command.get_options().iter().filter(|option| !option.is_hidden()) {
+            let (opts, help) = option.get_help_record();
+            out.push(".TP".to_string());
+            out.push(format!("\\fB{}\\fR", escape(opts.as_slice())));
+            out.push(escape(help.as_slice()));
+        }
+
+        if command.has_commands() {
+            out.push(".SH COMMANDS".to_string());
+            let subcommands = command.get_commands().iter().filter(|command| !command.is_hidden());
+            for subcommand in subcommands {
+                out.push(".TP".to_string());
+                out.push(format!("\\fB{}\\fR", escape(subcommand.get_name())));
+                out.push(escape(subcommand.get_short_help().as_slice()));
+            }
+        }
+
+        if !command.get_epilog().is_empty() {
+            out.push(".PP".to_string());
+            out.push(escape(command.get_epilog()));
+        }
+
+        let mut see_also: Vec<String> = Vec::new();
+        if path.len() > 1 {
+            see_also.push(path[..path.len() - 1].connect("-"));
+        }
+        for subcommand in command.get_commands().iter().filter(|command| !command.is_hidden()) {
+            see_also.push(format!("{}-{}", page_name, subcommand.get_name()));
+        }
+        if !see_also.is_empty() {
+            out.push(".SH SEE ALSO".to_string());
+            let refs: Vec<String> = see_also.iter().map(|name| {
+                format!("\\fB{}\\fR({})", escape(name.as_slice()), self.section)
+            }).collect();
+            out.push(refs.connect(", "));
+        }
+
+        out.push(String::new());
+        out.connect("\n")
+    }
+
+    fn collect_pages(&self, command: &Command, path: Vec<String>,
+                     pages: &mut Vec<(String, String)>) {
+        pages.push((path.connect("-"), self.render_page(command, &path)));
+        for subcommand in command.get_commands().iter().filter(|command| !command.is_hidden()) {
+            let mut subpath = path.clone();
+            subpath.push(subcommand.get_name().to_string());
+            self.collect_pages(subcommand, subpath, pages);
+        }
+    }
+
+    /// Renders all the pages, returns the page names and their contents.
+    pub fn render(&self, command: &Command) -> Vec<(String, String)> {
+        let mut pages: Vec<(String, String)> = Vec::new();
+        self.collect_pages(command, vec![command.get_name().to_string()], &mut pages);
+        pages
+    }
+
+    /// Writes all the pages into the given directory, eg. `tool-remove.1`.
+    pub fn write_pages(&self, command: &Command, directory: &Path) -> io::Result<()> {
+        for &(ref name, ref content) in self.render(command).iter() {
+            let filename = directory.join(format!("{}.{}", name, self.section));
+            let mut file = try!(File::create(&filename));
+            try!(file.write_all(content.as_bytes()));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,363 @@
+// This module implements the command line option parser.
+// Copyright (c) 2015 by Shipeng Feng.
+// Licensed under the BSD License, see LICENSE for more details.
+
+use std::io::Read;
+use std::fs::File;
+use std::path::Path;
+use std::collections::HashMap;
+use std::slice::SliceConcatExt;
+
+use types::{Options, TokenNormalizeFunc};
+use utils::{get_close_matches, normalize_token};
+
+
+/// The parsed options and the leftover positional arguments.
+pub struct ParseResult {
+    pub opts: HashMap<String, Vec<String>>,  // option name -> values, in the order given
+    pub largs: Vec<String>,  // the leftover arguments
+}
+
+
+/// The option parser is an internal type that splits the command line into
+/// option values and positional arguments.
+pub struct OptionParser {
+    options: Vec<Options>,
+    pub allow_interspersed_args: bool,  // stop at the first positional argument if false
+    pub negative_numbers: bool,  // treat unknown tokens like `-1.5` as positional arguments
+    pub ignore_unknown_options: bool,  // keep unknown options as positional arguments
+    pub token_normalize_func: Option<TokenNormalizeFunc>,  // applied before matching names
+}
+
+impl OptionParser {
+    /// Create one new option parser.
+    pub fn new() -> OptionParser {
+        OptionParser {
+            options: Vec::new(),
+            allow_interspersed_args: true,
+            negative_numbers: false,
+            ignore_unknown_options: false,
+            token_normalize_func: None,
+        }
+    }
+
+    /// Adds a new option to the parser.
+    pub fn add_option(&mut self, option: Options) {
+        self.options.push(option);
+    }
+
+    fn find_option(&self, opt: &str) -> Option<&Options> {
+        let opt = normalize_token(self.token_normalize_func, opt);
+        self.options.iter().find(|option| {
+            option.get_opts().iter().any(|name| {
+                normalize_token(self.token_normalize_func, name.as_slice()) == opt
+            })
+        })
+    }
+
+    /// Check the character starts an option, `-` or a custom prefix like `+`.
+    fn is_prefix_char(&self, c: char) -> bool {
+        c == '-' || self.options.iter().any(|option| {
+            option.get_opts().iter().any(|name| name.starts_with(c.to_string().as_slice()))
+        })
+    }
+
+    /// Check the argument is a whole option rather than a cluster of short
+    /// options, eg. `--name`, `++name`, `/debug` or `/debug=value`.
+    fn is_long_opt(&self, arg: &str) -> bool {
+        let mut chars = arg.chars();
+        if chars.next() == chars.next() {
+            return true;
+        }
+        let opt = match arg.find('=') {
+            Some(index) => &arg[..index],
+            None => arg,
+        };
+        self.find_option(opt).is_some()
+    }
+
+    /// Check the argument with a custom prefix like `+` or `/` is a declared
+    /// option or a cluster of declared short options, eg. `+w`, `/debug=1`
+    /// or `+wx`.  Anything else, like the path `/tmp/x`, is positional.
+    fn is_custom_opt(&self, arg: &str) -> bool {
+        let opt = match arg.find('=') {
+            Some(index) => &arg[..index],
+            None => arg,
+        };
+        if self.find_option(opt).is_some() {
+            return true;
+        }
+        let prefix = arg.chars().next().unwrap();
+        for ch in arg[prefix.len_utf8()..].chars() {
+            match self.find_option(format!("{}{}", prefix, ch).as_slice()) {
+                Some(option) if option.takes_value() => return true,
+                Some(_) => (),
+                None => return false,
+            }
+        }
+        true
+    }
+
+    fn no_such_option(&self, opt: &str) -> String {
+        let mut possibilities: Vec<String> = Vec::new();
+        for option in self.options.iter().filter(|option| !option.is_hidden()) {
+            for name in option.get_opts().into_iter() {
+                if name.chars().count() > 2 {
+                    possibilities.push(name);
+                }
+            }
+        }
+        let matches = get_close_matches(opt, &possibilities);
+        match matches.len() {
+            0 => format!("No such option: {}", opt),
+            1 => format!("No such option: {}  Did you mean {}?", opt, matches[0]),
+            _ => format!("No such option: {}  (Possible options: {})", opt, matches.connect(", ")),
+        }
+    }
+
+    fn add_value(result: &mut ParseResult, option: &Options, value: String) {
+        let name = option.get_name().to_string();
+        if !result.opts.contains_key(&name) {
+            result.opts.insert(name.clone(), Vec::new());
+        }
+        result.opts.get_mut(&name).unwrap().push(value);
+    }
+
+    /// Parses a long option, the value may be attached as `--name=value`.
+    fn match_long_opt<I>(&self, arg: &str, rargs: &mut I,
+                         result: &mut ParseResult) -> Result<(), String>
+        where I: Iterator<Item=String> {
+        let (opt, attached) = match arg.find('=') {
+            Some(index) => (&arg[..index], Some(arg[index + 1..].to_string())),
+            None => (arg, None),
+        };
+        let option = match self.find_option(opt) {
+            Some(option) => option,
+            None if self.ignore_unknown_options => {
+                result.largs.push(arg.to_string());
+                return Ok(());
+            },
+            None => return Err(self.no_such_option(opt)),
+        };
+        let value = if option.takes_value() {
+            match attached.or_else(|| rargs.next()) {
+                Some(value) => value,
+                None => return Err(format!("{} option requires an argument", opt)),
+            }
+        } else if attached.is_some() {
+            return Err(format!("{} option does not take a value", opt));
+        } else {
+            "true".to_string()
+        };
+        OptionParser::add_value(result, option, value);
+        Ok(())
+    }
+
+    /// Parses a cluster of short options like `-abc`.  An option that takes
+    /// a value uses the rest of the cluster, eg. `-ofile`, or the next
+    /// argument.  Ignored unknown options are kept together as one argument.
+    fn match_short_opts<I>(&self, arg: &str, rargs: &mut I,
+                           result: &mut ParseResult) -> Result<(), String>
+        where I: Iterator<Item=String> {
+        let prefix = arg.chars().next().unwrap();
+        let start = prefix.len_utf8();
+        let mut unknown = String::new();
+        for (index, ch) in arg[start..].char_indices() {
+            let opt = format!("{}{}", prefix, ch);
+            let option = match self.find_option(opt.as_slice()) {
+                Some(option) => option,
+                None if self.ignore_unknown_options => {
+                    unknown.push(ch);
+                    continue;
+                },
+                None => return Err(self.no_such_option(opt.as_slice())),
+            };
+            if option.takes_value() {
+                let rest = &arg[start + index + ch.len_utf8()..];
+                let value = if !rest.is_empty() {
+                    rest.to_string()
+                } else {
+                    match rargs.next() {
+                        Some(value) => value,
+                        None => return Err(format!("{} option requires an argument", opt)),
+                    }
+                };
+                OptionParser::add_value(result, option, value);
+                break;
+            }
+            OptionParser::add_value(result, option, "true".to_string());
+        }
+        if !unknown.is_empty() {
+            result.largs.push(format!("{}{}", prefix, unknown));
+        }
+        Ok(())
+    }
+
+    /// Parses the given arguments and returns the option values and the
+    /// leftover arguments.  Unknown options are reported as an error, unless
+    /// they are ignored.
+    pub fn parse_args(&self, args: Vec<String>) -> Result<ParseResult, String> {
+        let mut result = ParseResult {
+            opts: HashMap::new(),
+            largs: Vec::new(),
+        };
+        let mut rargs = args.into_iter();
+        loop {
+            let arg = match rargs.next() {
+                Some(arg) => arg,
+                None => break,
+            };
+            if arg.as_slice() == "--" {
+                // Everything after `--` is a positional argument.
+                result.largs.extend(rargs);
+                break;
+            }
+            let prefix = arg.chars().next().unwrap_or('-');
+            let positional = arg.chars().count() < 2 ||
+                !self.is_prefix_char(prefix) ||
+                (prefix != '-' && !self.is_custom_opt(arg.as_slice())) ||
+                (self.negative_numbers && is_negative_number(arg.as_slice()) &&
+                 self.find_option(arg.as_slice()).is_none());
+            if positional {
+                result.largs.push(arg);
+                if !self.allow_interspersed_args {
+                    result.largs.extend(rargs);
+                    break;
+                }
+                continue;
+            }
+            if self.is_long_opt(arg.as_slice()) {
+                try!(self.match_long_opt(arg.as_slice(), &mut rargs, &mut result));
+            } else {
+                try!(self.match_short_opts(arg.as_slice(), &mut rargs, &mut result));
+            }
+        }
+        Ok(result)
+    }
+}
+
+
+/// Check the token looks like a negative number, eg. `-1` or `-0.5`.
+pub fn is_negative_number(arg: &str) -> bool {
+    arg.starts_with("-") && arg[1..].parse::<f64>().is_ok()
+}
+
+
+/// How deep response files may include other response files.
+const MAX_RESPONSE_FILE_DEPTH: usize = 10;
+
+
+/// Splits text into arguments like a shell does.  Single quotes keep the
+/// text literally, double quotes and backslashes escape, and a `#` at the
+/// start of an argument comments out the rest of the line.
+pub fn split_args(text: &str) -> Result<Vec<String>, String> {
+    let mut args: Vec<String> = Vec::new();
+    let mut current = String::new();
+    let mut in_arg = false;
+    let mut chars = text.chars();
+    loop {
+        let c = match chars.next() {
+            Some(c) => c,
+            None => break,
+        };
+        match c {
+            '#' if !in_arg => {
+                // Skip the comment up to the end of the line.
+                loop {
+                    match chars.next() {
+                        Some('\n') | None => break,
+                        Some(_) => (),
+                    }
+                }
+            },
+            '\'' => {
+                in_arg = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => current.push(c),
+                        None => return Err("unterminated single quote".to_string()),
+                    }
+                }
+            },
+            '"' => {
+                in_arg = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) if c == '"' || c == '\\' || c == '$' || c == '`' => {
+                                current.push(c);
+                            },
+                            Some('\n') => (),
+                            Some(c) => { current.push('\\'); current.push(c); },
+                            None => return Err("unterminated double quote".to_string()),
+                        },
+                        Some(c) => current.push(c),
+                        None => return Err("unterminated double quote".to_string()),
+                    }
+                }
+            },
+            '\\' => {
+                match chars.next() {
+                    Some('\n') => (),
+                    Some(c) => { in_arg = true; current.push(c); },
+                    None => { in_arg = true; current.push('\\'); },
+                }
+            },
+            c if c.is_whitespace() => {
+                if in_arg {
+                    args.push(current);
+                    current = String::new();
+                    in_arg = false;
+                }
+            },
+            c => {
+                in_arg = true;
+                current.push(c);
+            },
+        }
+    }
+    if in_arg {
+        args.push(current);
+    }
+    Ok(args)
+}
+
+
+fn expand_response_files_at(args: Vec<String>, depth: usize) -> Result<Vec<String>, String> {
+    let mut expanded: Vec<String> = Vec::new();
+    for arg in args.into_iter() {
+        if arg.starts_with("@@") {
+            // An escaped literal leading `@`.
+            expanded.push(arg[1..].to_string());
+        } else if arg.starts_with("@") && arg.len() > 1 {
+            if depth >= MAX_RESPONSE_FILE_DEPTH {
+                return Err(format!("Response files nested too deeply at \"{}\".", arg));
+            }
+            let path = Path::new(&arg[1..]);
+            let mut text = String::new();
+            match File::open(&path).and_then(|mut file| file.read_to_string(&mut text)) {
+                Ok(_) => (),
+                Err(e) => return Err(format!("Could not read response file \"{}\": {}",
+                                             path.display(), e)),
+            }
+            let file_args = match split_args(text.as_slice()) {
+                Ok(file_args) => file_args,
+                Err(message) => return Err(format!("Invalid response file \"{}\": {}",
+                                                   path.display(), message)),
+            };
+            expanded.extend(try!(expand_response_files_at(file_args, depth + 1)).into_iter());
+        } else {
+            expanded.push(arg);
+        }
+    }
+    Ok(expanded)
+}
+
+
+/// Replaces `@path` arguments with the arguments in the file, recursively.
+/// Use `@@` for an argument that starts with a literal `@`.
+pub fn expand_response_files(args: Vec<String>) -> Result<Vec<String>, String> {
+    expand_response_files_at(args, 0)
+}
@@ -0,0 +1,128 @@
+// This module implements the machine-readable command description.
+// Copyright (c) 2015 by Shipeng Feng.
+// Licensed under the BSD License, see LICENSE for more details.
+
+use std::slice::SliceConcatExt;
+
+use core::Command;
+use types::{Options, Argument};
+
+
+/// A minimal JSON value, enough to describe the command tree.
+enum Json {
+    Null,
+    Bool(bool),
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(&'static str, Json)>),
+}
+
+fn quote(text: &str) -> String {
+    let mut quoted = String::from_str("\"");
+    for c in text.chars() {
+        match c {
+            '"'  => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(format!("\\u{:04x}", c as u32).as_slice()),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+impl Json {
+    fn encode(&self, indent: usize) -> String {
+        let padding: String = range(0, indent + 2).map(|_| ' ').collect();
+        let closing: String = range(0, indent).map(|_| ' ').collect();
+        match *self {
+            Json::Null => "null".to_string(),
+            Json::Bool(value) => format!("{}", value),
+            Json::Str(ref value) => quote(value.as_slice()),
+            Json::Array(ref items) => {
+                if items.is_empty() {
+                    return "[]".to_string();
+                }
+                let items: Vec<String> = items.iter().map(|item| {
+                    format!("{}{}", padding, item.encode(indent + 2))
+                }).collect();
+                format!("[\n{}\n{}]", items.connect(",\n"), closing)
+            },
+            Json::Object(ref fields) => {
+                let fields: Vec<String> = fields.iter().map(|&(ref key, ref value)| {
+                    format!("{}{}: {}", padding, quote(*key), value.encode(indent + 2))
+                }).collect();
+                format!("{{\n{}\n{}}}", fields.connect(",\n"), closing)
+            },
+        }
+    }
+}
+
+fn string(text: &str) -> Json {
+    Json::Str(text.to_string())
+}
+
+fn optional(value: Option<&str>) -> Json {
+    match value {
+        Some(value) => string(value),
+        None => Json::Null,
+    }
+}
+
+
+fn describe_option(option: &Options) -> Json {
+    Json::Object(vec![
+        ("short_name", string(option.get_short_name())),
+        ("long_name", string(option.get_long_name())),
+        ("opts", Json::Array(option.get_opts().into_iter().map(Json::Str).collect())),
+        ("help", string(option.get_help())),
+        ("type", string(option.get_type_name())),
+        ("is_flag", Json::Bool(option.is_flag())),
+        ("is_bool_flag", Json::Bool(option.is_bool_flag())),
+        ("multiple", Json::Bool(option.is_multiple())),
+        ("required", Json::Bool(option.is_required())),
+        ("default", optional(option.get_default_display().as_ref().map(|d| d.as_slice()))),
+        ("envvar", optional(option.get_envvar())),
+        ("choices", Json::Array(option.get_choices().iter().map(|c| string(*c)).collect())),
+        ("group", optional(option.get_group())),
+        ("hidden", Json::Bool(option.is_hidden())),
+        ("deprecated", Json::Bool(option.is_deprecated())),
+    ])
+}
+
+
+fn describe_argument(argument: &Argument) -> Json {
+    Json::Object(vec![
+        ("name", string(argument.get_name())),
+        ("type", string(argument.get_type_name())),
+        ("required", Json::Bool(argument.is_required())),
+        ("default", optional(argument.get_default_display().as_ref().map(|d| d.as_slice()))),
+        ("hidden", Json::Bool(argument.is_hidden())),
+        ("deprecated", Json::Bool(argument.is_deprecated())),
+    ])
+}
+
+
+fn describe_command(command: &Command) -> Json {
+    Json::Object(vec![
+        ("name", string(command.get_name())),
+        ("help", string(command.get_help_text())),
+        ("short_help", string(command.get_short_help().as_slice())),
+        ("epilog", string(command.get_epilog())),
+        ("usage", string(command.get_usage_pieces().connect(" ").as_slice())),
+        ("hidden", Json::Bool(command.is_hidden())),
+        ("deprecated", Json::Bool(command.is_deprecated())),
+        ("options", Json::Array(command.get_options().iter().map(describe_option).collect())),
+        ("arguments", Json::Array(command.get_arguments().iter().map(describe_argument).collect())),
+        ("commands", Json::Array(command.get_commands().iter().map(describe_command).collect())),
+    ])
+}
+
+
+/// Returns the full schema of a command and all its subcommands as JSON.
+pub fn dump_schema(command: &Command) -> String {
+    describe_command(command).encode(0)
+}
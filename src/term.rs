@@ -19,6 +19,8 @@ use libc;
 use libc::funcs::bsd44::ioctl;
 use time;
 
+use utils::display_width;
+
 pub use self::Color::{
     Black,
     Red,
@@ -31,6 +33,7 @@ pub use self::Color::{
 };
 
 
+#[derive(Clone, Copy)]
 pub enum Color {
     Black,
     Red,
@@ -487,7 +490,7 @@ impl ProgressBar {
         old_io::stdout().write_all(BEFORE_BAR.as_bytes()).unwrap();
         let last_line_width = self.last_line_width;
         let line = self.format_progress_line();
-        let line_width = line.len();
+        let line_width = display_width(line.as_slice());
         self.last_line_width = line_width;
         old_io::stdout().write_all(line.as_bytes()).unwrap();
         if last_line_width > line_width {
@@ -2,17 +2,149 @@
 // Copyright (c) 2015 by Shipeng Feng.
 // Licensed under the BSD License, see LICENSE for more details.
 
-use getopts;
+use std::collections::HashMap;
+use std::slice::SliceConcatExt;
 
+use parser::OptionParser;
+use core::Context;
+use completion::CompletionItem;
+use formatting::Theme;
 
-/// Command params type.
-pub type Params = Vec<String>;
+
+/// Where the value of a parameter came from.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ParamSource {
+    CommandLine,
+    Environment,
+    ConfigFile,
+    DefaultMap,
+    Default,
+}
+
+impl ParamSource {
+    /// Describes the source for help and messages.
+    pub fn describe(&self) -> &'static str {
+        match *self {
+            ParamSource::CommandLine => "command line",
+            ParamSource::Environment => "env var",
+            ParamSource::ConfigFile  => "config file",
+            ParamSource::DefaultMap  => "default map",
+            ParamSource::Default     => "default",
+        }
+    }
+}
+
+
+/// Command params type, holds the processed values of options and arguments
+/// by name and where they came from.  Options are named after their long
+/// name.
+#[derive(Clone)]
+pub struct Params {
+    values: HashMap<String, Vec<String>>,
+    sources: HashMap<String, ParamSource>,
+    extra_args: Vec<String>,
+}
+
+impl Params {
+    /// Create one new empty params.
+    pub fn new() -> Params {
+        Params {
+            values: HashMap::new(),
+            sources: HashMap::new(),
+            extra_args: Vec::new(),
+        }
+    }
+
+    /// Inserts or updates the values of a parameter.
+    pub fn insert(&mut self, name: &str, values: Vec<String>) {
+        self.values.insert(name.to_string(), values);
+    }
+
+    /// Inserts or updates the values of a parameter and records where they
+    /// came from.
+    pub fn insert_with_source(&mut self, name: &str, values: Vec<String>, source: ParamSource) {
+        self.values.insert(name.to_string(), values);
+        self.sources.insert(name.to_string(), source);
+    }
+
+    /// Sets the leftover arguments of the command.
+    pub fn set_extra_args(&mut self, args: Vec<String>) {
+        self.extra_args = args;
+    }
+
+    /// Returns the leftover arguments in the order given, useful to forward
+    /// them to a child process.
+    pub fn get_extra_args(&self) -> &Vec<String> {
+        &self.extra_args
+    }
+
+    /// Returns where the value of a parameter came from.
+    pub fn get_source(&self, name: &str) -> Option<ParamSource> {
+        self.sources.get(name).map(|source| *source)
+    }
+
+    /// Returns the last value of a parameter.
+    pub fn get(&self, name: &str) -> Option<String> {
+        match self.values.get(name) {
+            Some(values) => values.last().map(|value| value.clone()),
+            None => None,
+        }
+    }
+
+    /// Returns all the values of a parameter, useful for multiple options.
+    pub fn get_all(&self, name: &str) -> Vec<String> {
+        match self.values.get(name) {
+            Some(values) => values.clone(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Check a parameter has a value or not, useful for flags.
+    pub fn is_set(&self, name: &str) -> bool {
+        match self.values.get(name) {
+            Some(values) => !values.is_empty(),
+            None => false,
+        }
+    }
+}
 
 
 /// Command callback func type.
 pub type CommandCallback = fn(Params);
 
 
+/// Constraints between parameters of one command, validated after parsing.
+/// Parameters are named like in `Params`, options by their long name.  A
+/// parameter counts as given if it is set on the command line, by its env
+/// var or in the config file, but not by a default.
+pub enum Constraint {
+    MutuallyExclusive(Vec<&'static str>),  // at most one of them may be given
+    AllOrNone(Vec<&'static str>),  // either all of them or none may be given
+    AtLeastOne(Vec<&'static str>),  // at least one of them must be given
+    Requires(&'static str, &'static str),  // the first one may only be given with the second one
+}
+
+
+/// Parameter callback func type, it runs after the value is resolved with
+/// the context holding the previously processed params.  It returns the
+/// possibly transformed values, or an error message for a bad value.
+pub type ParamCallback = fn(&Context, Vec<String>) -> Result<Vec<String>, String>;
+
+
+/// Dynamic default func type, called only when no value is given.
+pub type DefaultCallback = fn() -> String;
+
+
+/// Token normalize func type, it maps option names, subcommand names and
+/// choice values to the form they are matched in, eg. lowercase.
+pub type TokenNormalizeFunc = fn(&str) -> String;
+
+
+/// Parameter completion func type, it receives the partially parsed context
+/// and the incomplete value and returns the completion items.
+pub type CompletionCallback = fn(&Context, &str) -> Vec<CompletionItem>;
+
+
 /// Options are usually optional values on the command line.
 #[derive(Clone)]
 pub struct Options {
@@ -24,6 +156,17 @@ pub struct Options {
     multiple: bool,
     required: bool,
     default: Option<&'static str>,
+    choices: Vec<&'static str>,
+    completion: Option<CompletionCallback>,
+    group: Option<&'static str>,
+    hidden: bool,
+    deprecated: bool,
+    deprecated_hint: Option<&'static str>,
+    envvar: Option<&'static str>,
+    default_callback: Option<(DefaultCallback, &'static str)>,
+    callback: Option<ParamCallback>,
+    eager: bool,
+    number: bool,
 }
 
 impl Options {
@@ -38,41 +181,262 @@ impl Options {
             multiple: multiple,
             required: required,
             default: default,
+            choices: Vec::new(),
+            completion: None,
+            group: None,
+            hidden: false,
+            deprecated: false,
+            deprecated_hint: None,
+            envvar: None,
+            default_callback: None,
+            callback: None,
+            eager: false,
+            number: false,
         }
     }
 
-    pub fn add_to_parser(&self, parser: &mut getopts::Options) {
-        if self.is_flag {
-            if !self.is_bool_flag {
-                parser.optflagopt(self.short_name, self.long_name, self.help, self.long_name);
-            } else if self.multiple {
-                parser.optflagmulti(self.short_name, self.long_name, self.help);
-            } else {
-                parser.optflag(self.short_name, self.long_name, self.help);
-            }
+    /// Enable or disable expecting a number as the value.  A value like `-1`
+    /// is then accepted instead of being taken for an option.
+    pub fn number(&mut self, number: bool) {
+        self.number = number;
+    }
+
+    pub fn is_number(&self) -> bool {
+        self.number
+    }
+
+    /// Sets a callback to validate or transform the values of this option.
+    pub fn callback(&mut self, callback: ParamCallback) {
+        self.callback = Some(callback);
+    }
+
+    pub fn get_callback(&self) -> Option<ParamCallback> {
+        self.callback
+    }
+
+    /// Processes this option before all the other parameters.
+    pub fn eager(&mut self, eager: bool) {
+        self.eager = eager;
+    }
+
+    pub fn is_eager(&self) -> bool {
+        self.eager
+    }
+
+    /// Computes the default when the option is absent, eg. the current user.
+    /// The help string is shown in help instead, eg. `"(current user)"`.
+    pub fn default_callback(&mut self, callback: DefaultCallback, help: &'static str) {
+        self.default_callback = Some((callback, help));
+    }
+
+    /// Returns the default value, calling the dynamic default if any.
+    pub fn get_default_value(&self) -> Option<String> {
+        match self.default_callback {
+            Some((callback, _)) => Some(callback()),
+            None => self.default.map(|default| default.to_string()),
+        }
+    }
+
+    /// Returns the default as shown in help.
+    pub fn get_default_display(&self) -> Option<String> {
+        match self.default_callback {
+            Some((_, help)) => Some(help.to_string()),
+            None => self.default.map(|default| default.to_string()),
+        }
+    }
+
+    /// Reads the value from this env var if it isn't given on the command
+    /// line.  Multiple values are separated by whitespace.
+    pub fn envvar(&mut self, envvar: &'static str) {
+        self.envvar = Some(envvar);
+    }
+
+    pub fn get_envvar(&self) -> Option<&'static str> {
+        self.envvar
+    }
+
+    /// Hides this option from help and completion, it is still accepted.
+    pub fn hidden(&mut self, hidden: bool) {
+        self.hidden = hidden;
+    }
+
+    /// Marks this option as deprecated, using it prints a warning with the
+    /// optional replacement hint, eg. `Some("--output")`.
+    pub fn deprecated(&mut self, hint: Option<&'static str>) {
+        self.deprecated = true;
+        self.deprecated_hint = hint;
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        self.hidden
+    }
+
+    pub fn is_deprecated(&self) -> bool {
+        self.deprecated
+    }
+
+    pub fn get_deprecated_hint(&self) -> Option<&'static str> {
+        self.deprecated_hint
+    }
+
+    /// Shows this option in its own help section, eg. "Output options".
+    pub fn group(&mut self, group: &'static str) {
+        self.group = Some(group);
+    }
+
+    pub fn get_group(&self) -> Option<&'static str> {
+        self.group
+    }
+
+    /// Restricts the values of this option to a fixed set.
+    pub fn choices(&mut self, choices: Vec<&'static str>) {
+        self.choices = choices;
+    }
+
+    /// Sets a custom completion function for the values of this option.
+    pub fn completion(&mut self, completion: CompletionCallback) {
+        self.completion = Some(completion);
+    }
+
+    pub fn get_completion(&self) -> Option<CompletionCallback> {
+        self.completion
+    }
+
+    pub fn add_to_parser(&self, parser: &mut OptionParser) {
+        parser.add_option(self.clone());
+    }
+
+    pub fn get_short_name(&self) -> &'static str {
+        self.short_name
+    }
+
+    pub fn get_long_name(&self) -> &'static str {
+        self.long_name
+    }
+
+    pub fn is_flag(&self) -> bool {
+        self.is_flag
+    }
+
+    pub fn is_bool_flag(&self) -> bool {
+        self.is_bool_flag
+    }
+
+    /// The name this option's values are stored under, the long name
+    /// without its `-` prefix, or the short name if there is no long name.
+    /// Names with a custom prefix are kept whole, so `+w` and `-w` differ.
+    pub fn get_name(&self) -> &'static str {
+        let name = if self.long_name.is_empty() { self.short_name } else { self.long_name };
+        match name.chars().next() {
+            Some(c) if c != '-' && !c.is_alphanumeric() => name,
+            _ => name.trim_left_matches('-'),
+        }
+    }
+
+    /// All the option strings, eg. `["-h", "--help"]`.  Names starting with
+    /// a custom prefix like `+w` or `/debug` are used as they are.
+    pub fn get_opts(&self) -> Vec<String> {
+        let mut opts: Vec<String> = Vec::new();
+        if !self.short_name.is_empty() {
+            opts.push(with_prefix(self.short_name, "-"));
+        }
+        if !self.long_name.is_empty() {
+            opts.push(with_prefix(self.long_name, "--"));
+        }
+        opts
+    }
+
+    /// How this option is shown in messages, eg. `--verbose` or `/debug`.
+    pub fn get_display_name(&self) -> String {
+        self.get_opts().pop().unwrap_or(String::new())
+    }
+
+    /// Check this option consumes a value from the command line.
+    pub fn takes_value(&self) -> bool {
+        !self.is_flag
+    }
+
+    pub fn get_help(&self) -> &'static str {
+        self.help
+    }
+
+    pub fn get_choices(&self) -> &Vec<&'static str> {
+        &self.choices
+    }
+
+    /// A short name for the kind of value this option takes.
+    pub fn get_type_name(&self) -> &'static str {
+        if self.is_flag && self.is_bool_flag {
+            "flag"
+        } else if !self.choices.is_empty() {
+            "choice"
+        } else if self.number {
+            "number"
         } else {
-            if self.required {
-                parser.reqopt(self.short_name, self.long_name, self.help, self.long_name);
-            } else if self.multiple {
-                parser.optmulti(self.short_name, self.long_name, self.help, self.long_name);
-            } else {
-                parser.optopt(self.short_name, self.long_name, self.help, self.long_name);
-            }
+            "text"
+        }
+    }
+
+    pub fn is_multiple(&self) -> bool {
+        self.multiple
+    }
+
+    pub fn is_required(&self) -> bool {
+        self.required
+    }
+
+    pub fn get_default(&self) -> Option<&'static str> {
+        self.default
+    }
+
+    /// Returns the placeholder for the value in help, eg. `TEXT`.
+    pub fn get_metavar(&self) -> Option<String> {
+        if !self.takes_value() {
+            None
+        } else if !self.choices.is_empty() {
+            Some(format!("[{}]", self.choices.connect("|")))
+        } else if self.number {
+            Some("NUMBER".to_string())
+        } else {
+            Some("TEXT".to_string())
         }
     }
 
     pub fn get_help_record(&self) -> (String, String) {
-        let mut options = String::from_str("");
-        options.push_str("-");
-        options.push_str(self.short_name);
-        options.push_str(", ");
-        options.push_str("--");
-        options.push_str(self.long_name);
+        self.get_styled_help_record(&Theme::plain(), self.get_default_display())
+    }
+
+    /// Returns the help record with option names, metavars and defaults
+    /// styled by the theme.  The shown default is passed in, as it may come
+    /// from the environment or a config file.
+    pub fn get_styled_help_record(&self, theme: &Theme,
+                                  default: Option<String>) -> (String, String) {
+        let names: Vec<String> = self.get_opts().iter().map(|opt| {
+            theme.style_option(opt.as_slice())
+        }).collect();
+        let mut options = names.connect(", ");
+        match self.get_metavar() {
+            Some(metavar) => {
+                options.push_str(" ");
+                options.push_str(theme.style_metavar(metavar.as_slice()).as_slice());
+            },
+            None => (),
+        }
 
         let mut extra = String::from_str("");
-        if self.default.is_some() {
-            extra.push_str("default: ");
-            extra.push_str(self.default.unwrap());
+        match self.envvar {
+            Some(envvar) => extra.push_str(format!("env var: {}", envvar).as_slice()),
+            None => (),
+        }
+        match default {
+            Some(ref default) if !default.is_empty() => {
+                if !extra.is_empty() {
+                    extra.push_str("; ");
+                }
+                extra.push_str("default: ");
+                extra.push_str(theme.style_default(default.as_slice()).as_slice());
+            },
+            _ => (),
         }
         if self.required {
             if extra.is_empty() {
@@ -82,7 +446,14 @@ impl Options {
             }
         }
         let mut help: String = self.help.to_string();
-        if self.help.len() != 0 {
+        if self.deprecated {
+            if help.is_empty() {
+                help.push_str("(Deprecated)");
+            } else {
+                help.push_str(" (Deprecated)");
+            }
+        }
+        if help.len() != 0 && !extra.is_empty() {
             help.push_str("  ");
         }
         if !extra.is_empty() {
@@ -94,11 +465,28 @@ impl Options {
 }
 
 
+/// Adds the default prefix to an option name unless it has its own.
+fn with_prefix(name: &str, prefix: &str) -> String {
+    match name.chars().next() {
+        Some(c) if !c.is_alphanumeric() => name.to_string(),
+        _ => format!("{}{}", prefix, name),
+    }
+}
+
+
 /// Arguments are positional parameters to a command.
+#[derive(Clone)]
 pub struct Argument {
     name: &'static str,
     required: bool,
     default: Option<&'static str>,
+    completion: Option<CompletionCallback>,
+    hidden: bool,
+    deprecated: bool,
+    deprecated_hint: Option<&'static str>,
+    default_callback: Option<(DefaultCallback, &'static str)>,
+    callback: Option<ParamCallback>,
+    number: bool,
 }
 
 impl Argument {
@@ -107,10 +495,106 @@ impl Argument {
             name: name,
             required: required,
             default: default,
+            completion: None,
+            hidden: false,
+            deprecated: false,
+            deprecated_hint: None,
+            default_callback: None,
+            callback: None,
+            number: false,
+        }
+    }
+
+    /// Enable or disable expecting a number as the value.  A value like `-1`
+    /// is then taken as this argument instead of an option.
+    pub fn number(&mut self, number: bool) {
+        self.number = number;
+    }
+
+    pub fn is_number(&self) -> bool {
+        self.number
+    }
+
+    /// A short name for the kind of value this argument takes.
+    pub fn get_type_name(&self) -> &'static str {
+        if self.number { "number" } else { "text" }
+    }
+
+    /// Sets a callback to validate or transform the value of this argument.
+    pub fn callback(&mut self, callback: ParamCallback) {
+        self.callback = Some(callback);
+    }
+
+    pub fn get_callback(&self) -> Option<ParamCallback> {
+        self.callback
+    }
+
+    /// Computes the default when the argument is absent.  The help string
+    /// describes the default in generated docs.
+    pub fn default_callback(&mut self, callback: DefaultCallback, help: &'static str) {
+        self.default_callback = Some((callback, help));
+    }
+
+    /// Returns the default value, calling the dynamic default if any.
+    pub fn get_default_value(&self) -> Option<String> {
+        match self.default_callback {
+            Some((callback, _)) => Some(callback()),
+            None => self.default.map(|default| default.to_string()),
         }
     }
 
-    pub fn add_to_parser(&self, parser: &mut getopts::Options) {
+    /// Returns the default as shown in help.
+    pub fn get_default_display(&self) -> Option<String> {
+        match self.default_callback {
+            Some((_, help)) => Some(help.to_string()),
+            None => self.default.map(|default| default.to_string()),
+        }
+    }
+
+    /// Hides this argument from the usage line and completion, it is still
+    /// accepted.
+    pub fn hidden(&mut self, hidden: bool) {
+        self.hidden = hidden;
+    }
+
+    /// Marks this argument as deprecated, passing it prints a warning with
+    /// the optional replacement hint.
+    pub fn deprecated(&mut self, hint: Option<&'static str>) {
+        self.deprecated = true;
+        self.deprecated_hint = hint;
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        self.hidden
+    }
+
+    pub fn is_deprecated(&self) -> bool {
+        self.deprecated
+    }
+
+    pub fn get_deprecated_hint(&self) -> Option<&'static str> {
+        self.deprecated_hint
+    }
+
+    /// Sets a custom completion function for the value of this argument.
+    pub fn completion(&mut self, completion: CompletionCallback) {
+        self.completion = Some(completion);
+    }
+
+    pub fn get_completion(&self) -> Option<CompletionCallback> {
+        self.completion
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn is_required(&self) -> bool {
+        self.required
+    }
+
+    pub fn get_default(&self) -> Option<&'static str> {
+        self.default
     }
 
     pub fn get_usage_piece(&self) -> String {
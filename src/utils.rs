@@ -2,7 +2,24 @@
 // Copyright (c) 2015 by Shipeng Feng.
 // Licensed under the BSD License, see LICENSE for more details.
 
+use std::cmp::{min, max};
+
 use term::{Color, Style};
+use types::TokenNormalizeFunc;
+
+
+/// Normalizes the token with the func if any.  The prefix of an option
+/// like `--` is kept, only the name is normalized.
+pub fn normalize_token(func: Option<TokenNormalizeFunc>, token: &str) -> String {
+    match func {
+        Some(func) => {
+            let name = token.trim_left_matches(|c: char| !c.is_alphanumeric());
+            let prefix = &token[..token.len() - name.len()];
+            format!("{}{}", prefix, func(name))
+        },
+        None => token.to_string(),
+    }
+}
 
 
 /// Styles the message with ANSI styles and println it.
@@ -18,3 +35,109 @@ pub fn sprintln(message: String, fg: Color, bg: Color, bold: bool, dim: bool,
     text.reverse(reverse);
     println!("{}", text);
 }
+
+
+/// Returns the edit distance between two strings.
+pub fn levenshtein(a: &str, b: &str) -> usize {
+    let b_chars: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = range(0, b_chars.len() + 1).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current: Vec<usize> = vec![i + 1];
+        for (j, b_char) in b_chars.iter().enumerate() {
+            let cost = if a_char == *b_char { 0 } else { 1 };
+            let value = min(min(previous[j + 1] + 1, current[j] + 1), previous[j] + cost);
+            current.push(value);
+        }
+        previous = current;
+    }
+    previous[b_chars.len()]
+}
+
+
+/// Returns the possibilities that are close enough to the word, sorted by
+/// edit distance, the closest first.  The allowed distance grows with the
+/// length of the word and is always smaller than it.
+pub fn get_close_matches(word: &str, possibilities: &Vec<String>) -> Vec<String> {
+    let len = word.chars().count();
+    let cutoff = if len < 2 { 0 } else { min(max(len / 3, 1), len - 1) };
+    let mut scored: Vec<(usize, String)> = Vec::new();
+    for possibility in possibilities.iter() {
+        let distance = levenshtein(word, possibility.as_slice());
+        if distance <= cutoff {
+            scored.push((distance, possibility.clone()));
+        }
+    }
+    scored.sort();
+    scored.into_iter().map(|(_, possibility)| possibility).collect()
+}
+
+
+/// Ranges of characters that take no column in the terminal.
+const ZERO_WIDTH: &'static [(u32, u32)] = &[
+    (0x0300, 0x036F), (0x0483, 0x0489), (0x0591, 0x05BD), (0x0610, 0x061A),
+    (0x064B, 0x065F), (0x0E31, 0x0E31), (0x0E34, 0x0E3A), (0x0E47, 0x0E4E),
+    (0x1AB0, 0x1AFF), (0x1DC0, 0x1DFF), (0x200B, 0x200F), (0x202A, 0x202E),
+    (0x2060, 0x2064), (0x20D0, 0x20FF), (0xFE00, 0xFE0F), (0xFE20, 0xFE2F),
+    (0xFEFF, 0xFEFF), (0xE0100, 0xE01EF),
+];
+
+/// Ranges of East Asian wide and fullwidth characters and emoji, which take
+/// two columns in the terminal.
+const DOUBLE_WIDTH: &'static [(u32, u32)] = &[
+    (0x1100, 0x115F), (0x231A, 0x231B), (0x2329, 0x232A), (0x23E9, 0x23EC),
+    (0x2614, 0x2615), (0x2648, 0x2653), (0x26AA, 0x26AB), (0x26BD, 0x26BE),
+    (0x26C4, 0x26C5), (0x26F2, 0x26F3), (0x2705, 0x2705), (0x270A, 0x270B),
+    (0x274C, 0x274C), (0x2753, 0x2755), (0x2795, 0x2797), (0x2B1B, 0x2B1C),
+    (0x2E80, 0x303E), (0x3041, 0x33FF), (0x3400, 0x4DBF), (0x4E00, 0x9FFF),
+    (0xA000, 0xA4CF), (0xA960, 0xA97F), (0xAC00, 0xD7A3), (0xF900, 0xFAFF),
+    (0xFE10, 0xFE19), (0xFE30, 0xFE6F), (0xFF00, 0xFF60), (0xFFE0, 0xFFE6),
+    (0x1F300, 0x1F64F), (0x1F680, 0x1F6FF), (0x1F900, 0x1F9FF),
+    (0x20000, 0x2FFFD), (0x30000, 0x3FFFD),
+];
+
+fn in_ranges(c: u32, ranges: &[(u32, u32)]) -> bool {
+    ranges.iter().any(|&(start, end)| c >= start && c <= end)
+}
+
+/// Returns the number of columns a character takes in the terminal.
+pub fn char_width(c: char) -> usize {
+    let c = c as u32;
+    if c < 0x20 || (c >= 0x7F && c < 0xA0) || in_ranges(c, ZERO_WIDTH) {
+        0
+    } else if in_ranges(c, DOUBLE_WIDTH) {
+        2
+    } else {
+        1
+    }
+}
+
+
+/// Returns the number of columns a text takes in the terminal.  ANSI escape
+/// sequences are ignored, East Asian wide characters count twice, and
+/// combining characters don't count.
+pub fn display_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut chars = text.chars();
+    loop {
+        match chars.next() {
+            Some('\x1b') => {
+                // Skip `ESC [ params final` sequences.
+                match chars.next() {
+                    Some('[') => {
+                        loop {
+                            match chars.next() {
+                                Some(c) if c >= '@' && c <= '~' => break,
+                                Some(_) => (),
+                                None => break,
+                            }
+                        }
+                    },
+                    _ => (),
+                }
+            },
+            Some(c) => width = width + char_width(c),
+            None => break,
+        }
+    }
+    width
+}
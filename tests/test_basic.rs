@@ -2,7 +2,614 @@
 // Copyright (c) 2015 by Shipeng Feng.
 // Licensed under the BSD License, see LICENSE for more details.
 
+extern crate cli;
+
+use std::env;
+use std::ascii::AsciiExt;
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::Write;
+
+
 #[test]
 fn foo() {
     assert!(true);
 }
+
+
+fn noop(_: cli::Params) {}
+
+
+fn args(items: &[&str]) -> Vec<String> {
+    items.iter().map(|item| item.to_string()).collect()
+}
+
+
+thread_local!(static RECORDED: RefCell<Option<cli::Params>> = RefCell::new(None));
+
+
+/// Command callback that keeps its params for `run_recorded`.
+fn record(params: cli::Params) {
+    RECORDED.with(|recorded| *recorded.borrow_mut() = Some(params));
+}
+
+
+/// Invokes the command and returns the params its `record` callback got,
+/// failing if the callback didn't run.
+fn run_recorded(command: &cli::Command, items: &[&str],
+                defaults: cli::DefaultMap) -> cli::Params {
+    command.invoke_with_defaults(command.get_name().to_string(), args(items), defaults).unwrap();
+    RECORDED.with(|recorded| recorded.borrow_mut().take()).expect("callback did not run")
+}
+
+
+#[test]
+fn test_command_aliases_in_help() {
+    let mut tool = cli::Command::new("tool", noop);
+    let mut remove = cli::Command::new("remove", noop);
+    remove.alias("rm");
+    remove.short_help("Remove a file.");
+    tool.add_command(remove);
+    let help = tool.get_help();
+    assert!(help.contains("remove (rm)"));
+    assert!(help.contains("COMMAND [ARGS]..."));
+}
+
+
+#[test]
+fn test_resolve_command() {
+    let mut tool = cli::Command::new("tool", noop);
+    let mut remove = cli::Command::new("remove", noop);
+    remove.alias("rm");
+    tool.add_command(remove);
+    tool.add_command(cli::Command::new("status", noop));
+    tool.add_command(cli::Command::new("stash", noop));
+    assert_eq!(tool.resolve_command("rm").ok().unwrap().get_name(), "remove");
+    assert!(tool.resolve_command("stat").is_err());
+    tool.allow_prefix_match(true);
+    assert_eq!(tool.resolve_command("stat").ok().unwrap().get_name(), "status");
+    assert_eq!(tool.resolve_command("re").ok().unwrap().get_name(), "remove");
+    assert_eq!(tool.resolve_command("st").err().unwrap(),
+               "Too many matches for \"st\": stash, status");
+}
+
+
+#[test]
+fn test_did_you_mean() {
+    let mut tool = cli::Command::new("tool", noop);
+    tool.option("v", "verbose", "", true, true, false, false, None);
+    tool.add_command(cli::Command::new("status", noop));
+    let message = tool.resolve_command("stauts").err().unwrap();
+    assert_eq!(message, "No such command \"stauts\".  Did you mean \"status\"?");
+    tool.add_command(cli::Command::new("rm", noop));
+    let message = tool.resolve_command("xy").err().unwrap();
+    assert_eq!(message, "No such command \"xy\".");
+    let message = tool.resolve_command("rn").err().unwrap();
+    assert_eq!(message, "No such command \"rn\".  Did you mean \"rm\"?");
+    let error = tool.invoke("tool".to_string(), args(&["--verbos"])).err().unwrap();
+    assert!(error.starts_with("Usage: tool [OPTIONS] COMMAND [ARGS]..."));
+    assert!(error.contains("Error: No such option: --verbos  Did you mean --verbose?"));
+}
+
+
+#[test]
+fn test_man_pages() {
+    let mut tool = cli::Command::new("tool", noop);
+    tool.help("Manage files.");
+    let mut remove = cli::Command::new("remove", noop);
+    remove.short_help("Remove a file.");
+    tool.add_command(remove);
+    let pages = cli::ManPage::new("0.1.0").render(&tool);
+    assert_eq!(pages.len(), 2);
+    assert_eq!(pages[1].0, "tool-remove".to_string());
+    assert!(pages[0].1.contains(".SH DESCRIPTION\nManage files."));
+    assert!(pages[1].1.contains("\\fBtool\\fR(1)"));
+}
+
+
+#[test]
+fn test_markdown_docs() {
+    let mut tool = cli::Command::new("tool", noop);
+    tool.option("v", "verbose", "Be verbose.", true, true, false, false, None);
+    tool.add_command(cli::Command::new("remove", noop));
+    let markdown = cli::to_markdown(&tool);
+    assert!(markdown.contains("| `-v, --verbose` | Be verbose. | flag |  |  | no |"));
+    assert!(markdown.contains("- [remove](#tool-remove)"));
+    assert!(markdown.contains("<a name=\"tool-remove\"></a>"));
+}
+
+
+#[test]
+fn test_docs_arguments() {
+    let mut tool = cli::Command::new("tool", noop);
+    tool.argument("secret", true, None).hidden(true);
+    assert!(!cli::to_markdown(&tool).contains("**Arguments**"));
+    assert!(!cli::to_html(&tool).contains("<h4>Arguments</h4>"));
+    tool.argument("user", false, None).default_callback(current_user, "(current user)");
+    assert!(cli::to_markdown(&tool).contains("- `user` (optional, default: (current user))"));
+    assert!(!cli::to_markdown(&tool).contains("secret"));
+    assert!(cli::to_html(&tool)
+                .contains("<li><code>user</code> (optional, default: (current user))</li>"));
+}
+
+
+#[test]
+fn test_schema() {
+    let mut tool = cli::Command::new("tool", noop);
+    tool.help("Say \"hi\".");
+    tool.option("c", "color", "", false, false, false, false, Some("red"))
+        .choices(vec!["red", "blue"]);
+    tool.option("v", "", "", true, true, false, false, None);
+    tool.argument("count", true, None).number(true);
+    let schema = tool.get_schema();
+    assert!(schema.contains("\"help\": \"Say \\\"hi\\\".\""));
+    assert!(schema.contains("\"default\": \"red\""));
+    assert!(schema.contains("\"type\": \"choice\""));
+    assert!(schema.contains("\"long_name\": \"color\""));
+    assert!(schema.contains("\"long_name\": \"\""));
+    assert!(!schema.contains("\"long_name\": \"v\""));
+    assert!(schema.contains("\"type\": \"number\""));
+}
+
+
+#[test]
+fn test_dump_schema_option_hidden() {
+    let mut tool = cli::Command::new("tool", noop);
+    tool.dump_schema_option(true);
+    let error = tool.invoke("tool".to_string(), args(&["--dump-schem"])).err().unwrap();
+    assert!(error.contains("Error: No such option: --dump-schem"));
+    assert!(!error.contains("Did you mean"));
+}
+
+
+#[test]
+fn test_help_text_wrapping() {
+    let mut tool = cli::Command::new("tool", noop);
+    tool.help("This is a very long help text that goes on and on, way beyond the \
+               width of any sensible terminal, so it has to be wrapped.\n\n\
+               \x08\n  keep   this\n  layout");
+    let help = tool.get_help();
+    for line in help.lines() {
+        assert!(line.chars().count() <= 80);
+    }
+    assert!(help.contains("  keep   this\n    layout"));
+}
+
+
+#[test]
+fn test_help_width() {
+    let mut tool = cli::Command::new("tool", noop);
+    tool.help("word word word word word word word word word word word word word word word \
+               word word word word word word word word word word word word word word word");
+    let longest = |tool: &cli::Command| {
+        tool.get_help().lines().map(|line| line.chars().count()).max().unwrap()
+    };
+    tool.terminal_width(200);
+    tool.max_content_width(120);
+    assert!(longest(&tool) > 80 && longest(&tool) <= 118);
+    tool.terminal_width(60);
+    assert!(longest(&tool) <= 58);
+    // Very narrow terminals still get a usable width.
+    tool.terminal_width(20);
+    assert!(longest(&tool) > 40 && longest(&tool) <= 50);
+}
+
+
+#[test]
+fn test_options_aligned() {
+    let mut tool = cli::Command::new("tool", noop);
+    tool.option("v", "verbose", "Be verbose.", true, true, false, false, None);
+    tool.option("o", "output-directory-for-everything", "Where to write.",
+                false, false, false, false, None);
+    let help = tool.get_help();
+    let column = |text: &str| {
+        help.lines().find(|line| line.contains(text)).unwrap().find(text).unwrap()
+    };
+    assert_eq!(column("Be verbose."), 34);
+    assert_eq!(column("Where to write."), 34);
+    assert_eq!(column("Show this message"), 34);
+    assert!(help.contains("  -o, --output-directory-for-everything TEXT\n"));
+}
+
+
+#[test]
+fn test_display_width() {
+    assert_eq!(cli::display_width("hello"), 5);
+    assert_eq!(cli::display_width("你好"), 4);
+    assert_eq!(cli::display_width("e\u{301}"), 1);
+    assert_eq!(cli::display_width("\x1b[31mred\x1b[0m"), 3);
+}
+
+
+#[test]
+fn test_help_sections() {
+    let mut tool = cli::Command::new("tool", noop);
+    tool.option("H", "host", "Server host.", false, false, false, false, None)
+        .group("Connection options");
+    tool.section("Examples", "tool --host example.com");
+    tool.section_order(vec!["Examples"]);
+    let help = tool.get_help();
+    let examples = help.find("Examples").unwrap();
+    let connection = help.find("Connection options").unwrap();
+    let options = help.find("\nOptions").unwrap();
+    assert!(examples < connection && connection < options);
+    assert!(help.find("--host").unwrap() < options);
+}
+
+
+#[test]
+fn test_hidden_and_deprecated() {
+    let mut tool = cli::Command::new("tool", noop);
+    tool.option("", "secret", "Internal.", true, true, false, false, None).hidden(true);
+    tool.option("", "out", "Output file.", false, false, false, false, None)
+        .deprecated(Some("--output"));
+    let mut old = cli::Command::new("old", noop);
+    old.deprecated(None);
+    old.short_help("Old stuff.");
+    tool.add_command(old);
+    let mut internal = cli::Command::new("internal", noop);
+    internal.hidden(true);
+    tool.add_command(internal);
+    let help = tool.get_help();
+    assert!(!help.contains("--secret"));
+    assert!(!help.contains("internal"));
+    assert!(help.contains("Output file. (Deprecated)"));
+    assert!(help.contains("(Deprecated) Old stuff."));
+    // Hidden names are not suggested either.
+    assert_eq!(tool.resolve_command("internl").err().unwrap(),
+               "No such command \"internl\".");
+    let error = tool.invoke("tool".to_string(), args(&["--secrt"])).err().unwrap();
+    assert!(error.contains("Error: No such option: --secrt\n"));
+}
+
+
+#[test]
+fn test_hidden_commands_prefix_match() {
+    let mut tool = cli::Command::new("tool", noop);
+    tool.allow_prefix_match(true);
+    tool.add_command(cli::Command::new("install", noop));
+    let mut internal = cli::Command::new("internal", noop);
+    internal.hidden(true);
+    tool.add_command(internal);
+    let mut debug = cli::Command::new("debug", noop);
+    debug.hidden(true);
+    tool.add_command(debug);
+    assert_eq!(tool.resolve_command("in").ok().unwrap().get_name(), "install");
+    assert!(tool.resolve_command("deb").is_err());
+    assert_eq!(tool.resolve_command("internal").ok().unwrap().get_name(), "internal");
+    assert_eq!(tool.resolve_command("debug").ok().unwrap().get_name(), "debug");
+}
+
+
+#[test]
+fn test_constraints_in_help() {
+    let mut tool = cli::Command::new("tool", noop);
+    tool.option("j", "json", "", true, true, false, false, None);
+    tool.option("y", "yaml", "", true, true, false, false, None);
+    tool.constraint(cli::Constraint::MutuallyExclusive(vec!["json", "yaml"]));
+    tool.show_constraints(true);
+    assert!(tool.get_help().contains("--json and --yaml are mutually exclusive."));
+}
+
+
+#[test]
+fn test_constraint_violations() {
+    let mut tool = cli::Command::new("tool", noop);
+    tool.option("j", "json", "", true, true, false, false, None);
+    tool.option("y", "yaml", "", true, true, false, false, None);
+    tool.option("u", "user", "", false, false, false, false, None)
+        .envvar("CLI_TEST_CONSTRAINT_USER");
+    tool.option("p", "password", "", false, false, false, false, None);
+    tool.constraint(cli::Constraint::MutuallyExclusive(vec!["json", "yaml"]));
+    tool.constraint(cli::Constraint::AtLeastOne(vec!["json", "yaml"]));
+    tool.constraint(cli::Constraint::Requires("password", "user"));
+    let error = |argv: &[&str]| {
+        tool.invoke("tool".to_string(), args(argv)).err().unwrap_or(String::new())
+    };
+    assert!(error(&["-j", "-y"]).contains("Error: --json and --yaml are mutually exclusive."));
+    assert!(error(&[]).contains("Error: At least one of --json or --yaml is required."));
+    assert!(error(&["-j", "-p", "x"]).contains("Error: --password requires --user."));
+    assert_eq!(error(&["-j", "-p", "x", "-u", "me"]), "");
+    // A value from the environment counts as given.
+    env::set_var("CLI_TEST_CONSTRAINT_USER", "me");
+    assert_eq!(error(&["-j", "-p", "x"]), "");
+
+    let mut login = cli::Command::new("login", noop);
+    login.option("u", "user", "", false, false, false, false, Some("guest"));
+    login.option("p", "password", "", false, false, false, false, None);
+    login.constraint(cli::Constraint::AllOrNone(vec!["user", "password"]));
+    let error = login.invoke("login".to_string(), args(&["-p", "x"])).err().unwrap();
+    assert!(error.contains("Error: --user and --password must be given together, \
+                            missing --user."));
+    assert!(login.invoke("login".to_string(), args(&[])).is_ok());
+}
+
+
+#[test]
+fn test_config_parse() {
+    let config = cli::Config::parse("verbose = true\n\n# Comment\n[remote.add]\n\
+                                     exclude_files = [\"a\", 'b']\n").ok().unwrap();
+    assert_eq!(config.get("", "verbose"), Some(vec!["true".to_string()]));
+    assert_eq!(config.get("remote.add", "exclude-files"),
+               Some(vec!["a".to_string(), "b".to_string()]));
+    assert_eq!(config.get("remote", "exclude-files"), None);
+}
+
+
+fn write_temp_file(name: &str, text: &str) -> String {
+    let path = env::temp_dir().join(name);
+    File::create(&path).unwrap().write_all(text.as_bytes()).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+
+fn check_precedence(params: cli::Params) {
+    assert_eq!(params.get("name"), Some("cli".to_string()));
+    assert_eq!(params.get_source("name"), Some(cli::ParamSource::CommandLine));
+    assert_eq!(params.get("color"), Some("green".to_string()));
+    assert_eq!(params.get_source("color"), Some(cli::ParamSource::Environment));
+    assert_eq!(params.get("size"), Some("large".to_string()));
+    assert_eq!(params.get_source("size"), Some(cli::ParamSource::ConfigFile));
+    assert_eq!(params.get("shape"), Some("round".to_string()));
+    assert_eq!(params.get_source("shape"), Some(cli::ParamSource::Default));
+    assert_eq!(params.get_all("tags"), vec!["a", "b", "c"]);
+}
+
+
+fn make_precedence_tool(app_name: &str) -> cli::Command {
+    let mut tool = cli::Command::new("tool", record);
+    tool.config_file(app_name);
+    tool.option("n", "name", "", false, false, false, false, Some("default"))
+        .envvar("CLI_TEST_PRECEDENCE_NAME");
+    tool.option("c", "color", "", false, false, false, false, Some("blue"))
+        .envvar("CLI_TEST_PRECEDENCE_COLOR");
+    tool.option("s", "size", "", false, false, false, false, Some("small"));
+    tool.option("", "shape", "", false, false, false, false, Some("round"));
+    tool.option("t", "tags", "", false, false, true, false, None)
+        .envvar("CLI_TEST_PRECEDENCE_TAGS");
+    tool
+}
+
+
+#[test]
+fn test_config_precedence() {
+    let path = write_temp_file("cli-test-precedence.toml",
+                               "name = \"config\"\ncolor = \"red\"\nsize = \"large\"\n");
+    env::set_var("CLI_TEST_PRECEDENCE_NAME", "env");
+    env::set_var("CLI_TEST_PRECEDENCE_COLOR", "green");
+    env::set_var("CLI_TEST_PRECEDENCE_TAGS", " a  b\tc ");
+
+    // The file given by `--config`.
+    let tool = make_precedence_tool("cli-test-precedence");
+    check_precedence(run_recorded(&tool, &["--config", path.as_slice(), "--name", "cli"],
+                                  cli::DefaultMap::new()));
+
+    // The file given by the `APP_CONFIG` env var.
+    let tool = make_precedence_tool("cli-test-discovery");
+    env::set_var("CLI_TEST_DISCOVERY_CONFIG", path.as_slice());
+    check_precedence(run_recorded(&tool, &["--name", "cli"], cli::DefaultMap::new()));
+
+    // An explicitly given file has to exist.
+    let error = tool.invoke("tool".to_string(), args(&["--config", "/nonexistent/config"]));
+    assert!(error.err().unwrap().contains("Error: could not open /nonexistent/config"));
+}
+
+
+#[test]
+fn test_help_with_broken_config() {
+    let path = write_temp_file("cli-test-broken.toml", "name\n");
+    env::set_var("CLI_TEST_BROKEN_CONFIG", path.as_slice());
+    let mut tool = cli::Command::new("tool", noop);
+    tool.config_file("cli-test-broken");
+    tool.option("n", "name", "", false, false, false, false, None);
+    assert!(tool.invoke("tool".to_string(), args(&["--help"])).is_ok());
+    let error = tool.invoke("tool".to_string(), args(&["--name", "x"])).err().unwrap();
+    assert!(error.contains("line 1: expected \"key = value\""));
+}
+
+
+#[test]
+fn test_nested_default_map() {
+    let mut defaults = cli::DefaultMap::new();
+    defaults.set("branch", "top");
+    defaults.command("remote").command("add").set("branch", "main");
+    let mut ctx = cli::Context::new("tool");
+    ctx.default_map = Some(defaults.clone());
+    assert_eq!(ctx.lookup_default_map("branch"), Some(vec!["top".to_string()]));
+    assert_eq!(ctx.child("remote").lookup_default_map("branch"), None);
+    assert_eq!(ctx.child("remote").child("add").lookup_default_map("branch"),
+               Some(vec!["main".to_string()]));
+
+    let mut tool = cli::Command::new("tool", noop);
+    let mut remote = cli::Command::new("remote", noop);
+    let mut add = cli::Command::new("add", record);
+    add.option("b", "branch", "", false, false, false, false, Some("master"));
+    remote.add_command(add);
+    tool.add_command(remote);
+    let params = run_recorded(&tool, &["remote", "add"], defaults);
+    assert_eq!(params.get("branch"), Some("main".to_string()));
+    assert_eq!(params.get_source("branch"), Some(cli::ParamSource::DefaultMap));
+}
+
+
+fn current_user() -> String {
+    "alice".to_string()
+}
+
+
+#[test]
+fn test_dynamic_default_help() {
+    let mut tool = cli::Command::new("tool", noop);
+    tool.option("u", "user", "Login name.", false, false, false, false, None)
+        .default_callback(current_user, "(current user)");
+    assert!(tool.get_help().contains("[default: (current user)]"));
+}
+
+
+fn shout(_: &cli::Context, values: Vec<String>) -> Result<Vec<String>, String> {
+    Ok(values.into_iter().map(|value| format!("{}!", value)).collect())
+}
+
+
+fn positive(_: &cli::Context, values: Vec<String>) -> Result<Vec<String>, String> {
+    if values.iter().any(|value| value.starts_with("-")) {
+        return Err("must be positive".to_string());
+    }
+    Ok(values)
+}
+
+
+fn after_eager(ctx: &cli::Context, values: Vec<String>) -> Result<Vec<String>, String> {
+    if !ctx.params.is_set("eager") {
+        return Err("processed before --eager".to_string());
+    }
+    Ok(values)
+}
+
+
+#[test]
+fn test_param_callbacks() {
+    let mut tool = cli::Command::new("tool", record);
+    tool.option("f", "first", "", false, false, false, false, Some("1")).callback(after_eager);
+    tool.option("n", "name", "", false, false, false, false, None).callback(shout);
+    tool.argument("count", true, None).callback(positive);
+    tool.option("e", "eager", "", true, true, false, false, None).eager(true);
+    // `--first` is declared first, but its callback sees `--eager` already.
+    let params = run_recorded(&tool, &["-n", "x", "3", "-e"], cli::DefaultMap::new());
+    assert_eq!(params.get("name"), Some("x!".to_string()));
+    assert_eq!(params.get("count"), Some("3".to_string()));
+    let error = tool.invoke("tool".to_string(), args(&["-e", "--", "-3"])).err().unwrap();
+    assert!(error.contains("Error: Invalid value for \"count\": must be positive"));
+}
+
+
+#[test]
+fn test_param_sources() {
+    let mut tool = cli::Command::new("tool", record);
+    tool.option("n", "name", "", false, false, false, false, None);
+    tool.option("c", "color", "", false, false, false, false, Some("red"));
+    tool.option("s", "size", "", false, false, false, false, Some("10"));
+    let mut defaults = cli::DefaultMap::new();
+    defaults.set("color", "blue");
+    let params = run_recorded(&tool, &["--name", "x"], defaults);
+    assert_eq!(params.get_source("name"), Some(cli::ParamSource::CommandLine));
+    assert_eq!(params.get_source("color"), Some(cli::ParamSource::DefaultMap));
+    assert_eq!(params.get_source("size"), Some(cli::ParamSource::Default));
+    assert_eq!(params.get("color"), Some("blue".to_string()));
+}
+
+
+#[test]
+fn test_response_files() {
+    let args = cli::split_args("--name 'a b' \"c \\\"d\\\"\" # comment\n@@literal e\\ f").unwrap();
+    assert_eq!(args, vec!["--name", "a b", "c \"d\"", "@@literal", "e f"]);
+    assert!(cli::split_args("'open").is_err());
+    let expanded = cli::expand_response_files(vec!["@@x".to_string()]).unwrap();
+    assert_eq!(expanded, vec!["@x"]);
+}
+
+
+#[test]
+fn test_response_files_expand() {
+    let inner = write_temp_file("cli-test-inner.rsp", "--level 'very high'\n");
+    let outer = write_temp_file("cli-test-outer.rsp",
+                                format!("-v @{}\nlast", inner).as_slice());
+    let expanded = cli::expand_response_files(
+        args(&["first", format!("@{}", outer).as_slice()])).unwrap();
+    assert_eq!(expanded, vec!["first", "-v", "--level", "very high", "last"]);
+
+    // A file that includes itself hits the nesting limit.
+    let looping = format!("@{}", env::temp_dir().join("cli-test-loop.rsp").display());
+    write_temp_file("cli-test-loop.rsp", looping.as_slice());
+    let error = cli::expand_response_files(args(&[looping.as_slice()]));
+    assert!(error.err().unwrap().starts_with("Response files nested too deeply"));
+
+    let missing = format!("@{}", env::temp_dir().join("cli-test-missing.rsp").display());
+    let error = cli::expand_response_files(args(&[missing.as_slice()]));
+    assert!(error.err().unwrap().starts_with("Could not read response file"));
+}
+
+
+#[test]
+fn test_posix_parsing() {
+    let mut tool = cli::Command::new("tool", record);
+    tool.option("a", "all", "", true, true, false, false, None);
+    tool.option("b", "brief", "", true, true, false, false, None);
+    tool.option("o", "out", "", false, false, true, false, None);
+    tool.argument("offset", true, None).number(true);
+    tool.argument("rest", true, None);
+    let params = run_recorded(&tool, &["-abofile", "-3", "--out=other", "--", "--brief"],
+                              cli::DefaultMap::new());
+    assert!(params.is_set("all"));
+    assert!(params.is_set("brief"));
+    assert_eq!(params.get_all("out"), vec!["file", "other"]);
+    assert_eq!(params.get("offset"), Some("-3".to_string()));
+    assert_eq!(params.get("rest"), Some("--brief".to_string()));
+}
+
+
+#[test]
+fn test_extra_args() {
+    let mut tool = cli::Command::new("exec", record);
+    tool.option("v", "verbose", "", true, true, false, false, None);
+    tool.ignore_unknown_options(true);
+    tool.allow_extra_args(true);
+    let params = run_recorded(&tool, &["-v", "docker", "run", "-it", "--rm", "image"],
+                              cli::DefaultMap::new());
+    assert!(params.is_set("verbose"));
+    assert_eq!(*params.get_extra_args(), vec!["docker", "run", "-it", "--rm", "image"]);
+}
+
+
+#[test]
+fn test_option_prefixes() {
+    let mut tool = cli::Command::new("tool", record);
+    tool.option("+w", "", "Enable warnings.", true, true, false, false, None);
+    tool.option("", "/debug", "Debug output.", true, true, false, false, None);
+    assert_eq!(tool.get_options()[0].get_help_record().0, "+w");
+    assert_eq!(tool.get_options()[1].get_help_record().0, "/debug");
+    let params = run_recorded(&tool, &["+w", "/debug"], cli::DefaultMap::new());
+    assert!(params.is_set("+w"));
+    assert!(params.is_set("/debug"));
+}
+
+
+#[test]
+fn test_option_prefixes_distinct() {
+    let mut tool = cli::Command::new("tool", record);
+    tool.option("+w", "", "Enable warnings.", true, true, false, false, None);
+    tool.option("w", "", "Disable warnings.", true, true, false, false, None);
+    tool.option("", "/debug", "Debug output.", true, true, false, false, None);
+    tool.argument("path", true, None);
+
+    // A path isn't taken for an unknown `/` option.
+    let params = run_recorded(&tool, &["+w", "/tmp/x"], cli::DefaultMap::new());
+    assert!(params.is_set("+w"));
+    assert!(!params.is_set("w"));
+    assert_eq!(params.get("path"), Some("/tmp/x".to_string()));
+
+    let params = run_recorded(&tool, &["-w", "/tmp/y"], cli::DefaultMap::new());
+    assert!(params.is_set("w"));
+    assert!(!params.is_set("+w"));
+    assert_eq!(params.get("path"), Some("/tmp/y".to_string()));
+}
+
+
+fn lowercase(token: &str) -> String {
+    token.to_ascii_lowercase().replace("_", "-")
+}
+
+
+#[test]
+fn test_token_normalize_func() {
+    let mut tool = cli::Command::new("tool", noop);
+    tool.token_normalize_func(lowercase);
+    let mut status = cli::Command::new("status", record);
+    status.option("", "dry-run", "", true, true, false, false, None);
+    status.option("", "level", "", false, false, false, false, None).choices(vec!["low", "high"]);
+    tool.add_command(status);
+    let params = run_recorded(&tool, &["STATUS", "--Dry_Run", "--LEVEL", "High"],
+                              cli::DefaultMap::new());
+    assert!(params.is_set("dry-run"));
+    assert_eq!(params.get("level"), Some("high".to_string()));
+}